@@ -1,25 +1,143 @@
 //! ADC Module
 
-use anyhow::{Context, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, TrySendError};
+use std::sync::Arc;
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
 use rppal::i2c::I2c;
 
-use crate::{pin::PinType, utils::init_i2c};
+use crate::{
+    pin::PinType,
+    settings::Settings,
+    utils::{init_i2c, track},
+};
+
+// ADC Constants
+const REF_VOLTAGE: f32 = 3.3;
+const MAX_VALUE: f32 = 4095.0;
+
+fn adc_reg(adc_pin: PinType) -> u8 {
+    let channel = 7 - adc_pin.adc_channel();
+    channel | 16 // 0x10
+}
+
+fn read_reg(bus: &mut I2c, reg: u8) -> Result<u16> {
+    track(transfer_reg(bus, reg))
+}
+
+fn transfer_reg(bus: &mut I2c, reg: u8) -> Result<u16> {
+    bus.smbus_write_word(reg, 0)
+        .context("ADC READ INIT FAILED")?;
+
+    let value_h = bus
+        .smbus_read_byte(reg)
+        .context("ADC READ (MSByte) FAILED")? as u16;
+    let value_l = bus
+        .smbus_read_byte(reg)
+        .context("ADC READ (LSByte) FAILED")? as u16;
+
+    let value = ((value_h) << 8) + value_l;
+
+    Ok(value)
+}
+
+/// How oversampled ADC readings are combined by [`ADC::read_filtered`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ADCFilter {
+    /// Average of the samples `(default)`
+    #[default]
+    Mean,
+    /// Median of the samples, rejecting spikes
+    Median,
+}
+
+/// A filtered ADC reading returned by [`ADC::read_filtered`]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct FilteredValue {
+    /// The filtered channel value
+    ///
+    /// Range --> (0.0 - 4095.0)
+    pub value: f32,
+    /// Variance estimate of the raw samples
+    pub variance: f32,
+}
+
+/// Linear calibration applied to [`ADC::read_voltage`]
+///
+/// `calibrated = voltage * gain + offset`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ADCCalibration {
+    /// Gain applied to the measured voltage `(default: 1.0)`
+    pub gain: f32,
+    /// Offset in `V` added after the gain `(default: 0.0)`
+    pub offset: f32,
+}
+
+impl Default for ADCCalibration {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            offset: 0.0,
+        }
+    }
+}
+
+impl ADCCalibration {
+    /// Derive a calibration from two `(measured, actual)` voltage pairs
+    ///
+    /// `measured` should be read with the default calibration, `actual` with a multimeter.
+    pub fn two_point(low: (f32, f32), high: (f32, f32)) -> Result<Self> {
+        let (measured_low, actual_low) = low;
+        let (measured_high, actual_high) = high;
+        if (measured_high - measured_low).abs() < f32::EPSILON {
+            bail!("Calibration points should have different measured voltages")
+        }
+
+        let gain = (actual_high - actual_low) / (measured_high - measured_low);
+        let offset = actual_low - measured_low * gain;
+
+        Ok(Self { gain, offset })
+    }
+
+    /// Apply the calibration to a `voltage`
+    pub fn apply(&self, voltage: f32) -> f32 {
+        voltage * self.gain + self.offset
+    }
+}
 
 /// A robot-hat ADC
 #[derive(Debug)]
 pub struct ADC {
+    pin: PinType,
     reg: u8,
     bus: I2c,
+    ref_voltage: f32,
+    calibration: ADCCalibration,
+    samples: usize,
+    filter: ADCFilter,
+    smoothing: Option<f32>,
+    smoothed: Option<FilteredValue>,
 }
 
 impl ADC {
     /// Create a new robot-hat adc pin with [`PinType`]  *(A0-A7)*
     pub fn new(adc_pin: PinType) -> Result<Self> {
-        let channel = 7 - adc_pin.adc_channel();
-        let reg = channel | 16; // 0x10
-
+        let reg = adc_reg(adc_pin);
         let bus = init_i2c().context("I2C INIT FAILED")?;
-        let adc = Self { reg, bus };
+        let adc = Self {
+            pin: adc_pin,
+            reg,
+            bus,
+            ref_voltage: REF_VOLTAGE,
+            calibration: ADCCalibration::default(),
+            samples: 1,
+            filter: ADCFilter::default(),
+            smoothing: None,
+            smoothed: None,
+        };
 
         Ok(adc)
     }
@@ -28,31 +146,326 @@ impl ADC {
     ///
     /// Range --> (0 - 4095)
     pub fn read_value(&mut self) -> Result<u16> {
-        self.bus
-            .smbus_write_word(self.reg, 0)
-            .context("ADC READ INIT FAILED")?;
-
-        let value_h = self
-            .bus
-            .smbus_read_byte(self.reg)
-            .context("ADC READ (MSByte) FAILED")? as u16;
-        let value_l = self
-            .bus
-            .smbus_read_byte(self.reg)
-            .context("ADC READ (LSByte) FAILED")? as u16;
-
-        let value = ((value_h) << 8) + value_l;
-
-        Ok(value)
+        read_reg(&mut self.bus, self.reg)
     }
 
     /// Read the adc channel voltage
     ///
-    /// Range --> (0 - 3.3V)
+    /// Range --> (0 - reference voltage), with the [`ADCCalibration`] applied
     pub fn read_voltage(&mut self) -> Result<f32> {
         let value = self.read_value()?;
-        let value = (value as f32) * 3.3 / 4095.0;
+        let value = (value as f32) * self.ref_voltage / MAX_VALUE;
+
+        Ok(self.calibration.apply(value))
+    }
+
+    /// Set the reference voltage of the adc `(default: 3.3V)`
+    pub fn set_reference_voltage(&mut self, ref_voltage: f32) {
+        self.ref_voltage = ref_voltage;
+    }
+
+    /// Set the [`ADCCalibration`] of this channel
+    pub fn set_calibration(&mut self, calibration: ADCCalibration) {
+        self.calibration = calibration;
+    }
+
+    /// Get the [`ADCCalibration`] of this channel
+    pub fn calibration(&self) -> ADCCalibration {
+        self.calibration
+    }
+
+    /// Store the reference voltage and calibration of this channel in `settings`
+    pub fn save_calibration(&self, settings: &mut Settings) {
+        let key = self.settings_key();
+        settings.set(&format!("{}_ref_voltage", key), self.ref_voltage);
+        settings.set(&format!("{}_gain", key), self.calibration.gain);
+        settings.set(&format!("{}_offset", key), self.calibration.offset);
+    }
+
+    /// Load the reference voltage and calibration of this channel from `settings`
+    ///
+    /// Missing values keep their current setting.
+    pub fn load_calibration(&mut self, settings: &Settings) {
+        let key = self.settings_key();
+        if let Some(ref_voltage) = settings.get(&format!("{}_ref_voltage", key)) {
+            self.ref_voltage = ref_voltage;
+        }
+        if let Some(gain) = settings.get(&format!("{}_gain", key)) {
+            self.calibration.gain = gain;
+        }
+        if let Some(offset) = settings.get(&format!("{}_offset", key)) {
+            self.calibration.offset = offset;
+        }
+    }
+
+    fn settings_key(&self) -> String {
+        format!("adc_a{}", self.pin.adc_channel())
+    }
+
+    /// Set the number of samples taken per [`ADC::read_filtered`] call `(default: 1)`
+    pub fn set_oversampling(&mut self, samples: usize) {
+        self.samples = samples.max(1);
+    }
+
+    /// Set how the samples are combined by [`ADC::read_filtered`] `(default: ADCFilter::Mean)`
+    pub fn set_filter(&mut self, filter: ADCFilter) {
+        self.filter = filter;
+    }
+
+    /// Set the exponential smoothing factor applied across [`ADC::read_filtered`] calls `(default: None)`
+    ///
+    /// Range --> (0.0 - 1.0), lower values smooth more
+    pub fn set_smoothing(&mut self, alpha: Option<f32>) {
+        self.smoothing = alpha.map(|alpha| alpha.clamp(0.0, 1.0));
+        self.smoothed = None;
+    }
+
+    /// Read the adc channel value using the configured oversampling, filter and smoothing
+    ///
+    /// The variance is the sample variance of the oversampled values, or the exponentially
+    /// weighted variance when smoothing is enabled.
+    pub fn read_filtered(&mut self) -> Result<FilteredValue> {
+        let mut samples = (0..self.samples)
+            .map(|_| self.read_value().map(f32::from))
+            .collect::<Result<Vec<_>>>()?;
+
+        let n = samples.len() as f32;
+        let mean = samples.iter().sum::<f32>() / n;
+        let variance = if samples.len() > 1 {
+            samples.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / (n - 1.0)
+        } else {
+            0.0
+        };
+        let value = match self.filter {
+            ADCFilter::Mean => mean,
+            ADCFilter::Median => {
+                samples.sort_by(f32::total_cmp);
+                samples[samples.len() / 2]
+            }
+        };
+        let filtered = FilteredValue { value, variance };
+
+        let filtered = match (self.smoothing, self.smoothed) {
+            (Some(alpha), Some(prev)) => {
+                let diff = value - prev.value;
+                FilteredValue {
+                    value: prev.value + alpha * diff,
+                    variance: (1.0 - alpha) * (prev.variance + alpha * diff.powi(2)),
+                }
+            }
+            _ => filtered,
+        };
+        if self.smoothing.is_some() {
+            self.smoothed = Some(filtered);
+        }
+
+        Ok(filtered)
+    }
+}
+
+/// Values of several ADC channels read together by [`ADCBus::read_values`]
+#[derive(Clone, Debug)]
+pub struct ADCSnapshot {
+    /// When the read started
+    pub timestamp: Instant,
+    /// The channels that were read, in order
+    pub pins: Vec<PinType>,
+    /// The channel values, in the same order as `pins`
+    ///
+    /// Range --> (0 - 4095)
+    pub values: Vec<u16>,
+}
+
+impl ADCSnapshot {
+    /// Get the value read for `adc_pin`, if it was part of the snapshot
+    pub fn get(&self, adc_pin: PinType) -> Option<u16> {
+        self.pins
+            .iter()
+            .position(|&pin| pin == adc_pin)
+            .map(|i| self.values[i])
+    }
+}
+
+/// A single robot-hat I2C bus shared by all ADC channels
+#[derive(Debug)]
+pub struct ADCBus {
+    bus: I2c,
+}
+
+impl ADCBus {
+    /// Create a new shared bus for reading ADC channels
+    pub fn new() -> Result<Self> {
+        let bus = init_i2c().context("I2C INIT FAILED")?;
+
+        Ok(Self { bus })
+    }
+
+    /// Read a single adc channel with [`PinType`]  *(A0-A7)*
+    ///
+    /// Range --> (0 - 4095)
+    pub fn read_value(&mut self, adc_pin: PinType) -> Result<u16> {
+        read_reg(&mut self.bus, adc_reg(adc_pin))
+    }
+
+    /// Read any subset of the adc channels with [`PinType`]  *(A0-A7)* in one call
+    pub fn read_values(&mut self, adc_pins: &[PinType]) -> Result<ADCSnapshot> {
+        let timestamp = Instant::now();
+        let values = adc_pins
+            .iter()
+            .map(|&pin| {
+                self.read_value(pin)
+                    .with_context(|| format!("Reading {:?} failed", pin))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(ADCSnapshot {
+            timestamp,
+            pins: adc_pins.to_vec(),
+            values,
+        })
+    }
+
+    /// Read all 8 adc channels `A0-A7`
+    pub fn read_all(&mut self) -> Result<ADCSnapshot> {
+        self.read_values(&[
+            PinType::A0,
+            PinType::A1,
+            PinType::A2,
+            PinType::A3,
+            PinType::A4,
+            PinType::A5,
+            PinType::A6,
+            PinType::A7,
+        ])
+    }
+}
+
+/// A block of consecutive samples delivered by [`ADCStream`]
+#[derive(Clone, Debug)]
+pub struct ADCBlock {
+    /// The timestamped samples, one [`ADCSnapshot`] per sample period
+    pub samples: Vec<ADCSnapshot>,
+    /// Number of sample periods missed while collecting this block because the
+    /// I2C bus could not keep up
+    pub overruns: usize,
+    /// Number of blocks dropped before this one because they were not received in time
+    pub dropped_blocks: usize,
+}
+
+/// Continuous sampling of ADC channels at a fixed rate on a dedicated thread
+///
+/// Blocks are received with [`ADCStream::recv`] or by iterating over the stream.
+pub struct ADCStream {
+    stop: Arc<AtomicBool>,
+    blocks: Receiver<Result<ADCBlock>>,
+    handle: Option<JoinHandle<ADCBus>>,
+}
+
+impl ADCStream {
+    /// Start sampling `adc_pins` with [`PinType`]  *(A0-A7)* every `period`,
+    /// delivering `block_size` samples per [`ADCBlock`]
+    ///
+    /// Up to 16 blocks are buffered; blocks arriving while the buffer is full are dropped
+    /// and counted in [`ADCBlock::dropped_blocks`].
+    /// The stream stops after the first failed read, which is delivered as an error.
+    pub fn new(mut bus: ADCBus, adc_pins: &[PinType], period: Duration, block_size: usize) -> Self {
+        let adc_pins = adc_pins.to_vec();
+        let block_size = block_size.max(1);
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        let (tx, blocks) = sync_channel(16);
+
+        let handle = spawn(move || {
+            let mut next = Instant::now();
+            let mut block = ADCBlock {
+                samples: Vec::with_capacity(block_size),
+                overruns: 0,
+                dropped_blocks: 0,
+            };
+
+            while !stopped.load(Ordering::Relaxed) {
+                sleep(next.saturating_duration_since(Instant::now()));
+                match bus.read_values(&adc_pins) {
+                    Ok(snapshot) => block.samples.push(snapshot),
+                    Err(err) => {
+                        let _ = tx.send(Err(err));
+                        break;
+                    }
+                }
+
+                next += period;
+                let now = Instant::now();
+                if now > next && !period.is_zero() {
+                    let missed = ((now - next).as_nanos() / period.as_nanos()) as u32 + 1;
+                    block.overruns += missed as usize;
+                    next += period * missed;
+                }
+
+                if block.samples.len() >= block_size {
+                    let full = std::mem::replace(
+                        &mut block,
+                        ADCBlock {
+                            samples: Vec::with_capacity(block_size),
+                            overruns: 0,
+                            dropped_blocks: 0,
+                        },
+                    );
+                    // Never wait for a slow receiver, that would break the sample rate
+                    match tx.try_send(Ok(full)) {
+                        Ok(()) => {}
+                        Err(TrySendError::Full(Ok(full))) => {
+                            block.dropped_blocks = full.dropped_blocks + 1;
+                        }
+                        Err(_) => break,
+                    }
+                }
+            }
+
+            bus
+        });
+
+        Self {
+            stop,
+            blocks,
+            handle: Some(handle),
+        }
+    }
+
+    /// Block until the next [`ADCBlock`] is available
+    ///
+    /// Returns `None` once the stream has stopped.
+    pub fn recv(&self) -> Option<Result<ADCBlock>> {
+        self.blocks.recv().ok()
+    }
+
+    /// Get the next [`ADCBlock`] if one is available, without blocking
+    pub fn try_recv(&self) -> Option<Result<ADCBlock>> {
+        self.blocks.try_recv().ok()
+    }
+
+    /// Stop sampling and return the [`ADCBus`]
+    pub fn stop(mut self) -> Result<ADCBus> {
+        self.shutdown().context("ADC stream thread panicked")
+    }
+
+    fn shutdown(&mut self) -> Option<ADCBus> {
+        self.stop.store(true, Ordering::Relaxed);
+        // Unblock the sampling thread if it is waiting to deliver an error
+        while self.blocks.try_recv().is_ok() {}
+        self.handle.take().and_then(|handle| handle.join().ok())
+    }
+}
+
+impl Iterator for ADCStream {
+    type Item = Result<ADCBlock>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.recv()
+    }
+}
 
-        Ok(value)
+impl Drop for ADCStream {
+    fn drop(&mut self) {
+        self.shutdown();
     }
 }
@@ -0,0 +1,344 @@
+//! ADXL345 accelerometer Module
+
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use rppal::i2c::I2c;
+
+use crate::utils::open_i2c;
+
+// ADXL345 Constants
+const ADDR: u16 = 0x53;
+const DEVICE_ID: u8 = 0xE5;
+// g per LSB in full resolution mode
+const SCALE: f32 = 0.0039;
+
+// ADXL345 Registers
+const REG_DEVID: u8 = 0x00;
+const REG_THRESH_TAP: u8 = 0x1D;
+const REG_DUR: u8 = 0x21;
+const REG_LATENT: u8 = 0x22;
+const REG_WINDOW: u8 = 0x23;
+const REG_THRESH_FF: u8 = 0x28;
+const REG_TIME_FF: u8 = 0x29;
+const REG_TAP_AXES: u8 = 0x2A;
+const REG_BW_RATE: u8 = 0x2C;
+const REG_POWER_CTL: u8 = 0x2D;
+const REG_INT_ENABLE: u8 = 0x2E;
+const REG_INT_MAP: u8 = 0x2F;
+const REG_INT_SOURCE: u8 = 0x30;
+const REG_DATA_FORMAT: u8 = 0x31;
+const REG_DATAX0: u8 = 0x32;
+
+// Register bits
+const MEASURE: u8 = 0x08;
+const FULL_RES: u8 = 0x08;
+const INT_DATA_READY: u8 = 0x80;
+const INT_SINGLE_TAP: u8 = 0x40;
+const INT_DOUBLE_TAP: u8 = 0x20;
+const INT_FREE_FALL: u8 = 0x04;
+
+/// Measurement range of the [`ADXL345`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Range {
+    /// ±2g `(default)`
+    #[default]
+    G2,
+    /// ±4g
+    G4,
+    /// ±8g
+    G8,
+    /// ±16g
+    G16,
+}
+
+/// Output data rate of the [`ADXL345`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DataRate {
+    /// 6.25Hz
+    Hz6_25,
+    /// 12.5Hz
+    Hz12_5,
+    /// 25Hz
+    Hz25,
+    /// 50Hz
+    Hz50,
+    /// 100Hz `(default)`
+    #[default]
+    Hz100,
+    /// 200Hz
+    Hz200,
+    /// 400Hz
+    Hz400,
+    /// 800Hz
+    Hz800,
+    /// 1600Hz
+    Hz1600,
+    /// 3200Hz
+    Hz3200,
+}
+
+impl DataRate {
+    fn code(&self) -> u8 {
+        match self {
+            DataRate::Hz6_25 => 0x06,
+            DataRate::Hz12_5 => 0x07,
+            DataRate::Hz25 => 0x08,
+            DataRate::Hz50 => 0x09,
+            DataRate::Hz100 => 0x0A,
+            DataRate::Hz200 => 0x0B,
+            DataRate::Hz400 => 0x0C,
+            DataRate::Hz800 => 0x0D,
+            DataRate::Hz1600 => 0x0E,
+            DataRate::Hz3200 => 0x0F,
+        }
+    }
+}
+
+/// Which interrupt pin an [`ADXL345`] event is routed to
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum IntPin {
+    /// INT1 `(default)`
+    #[default]
+    Int1,
+    /// INT2
+    Int2,
+}
+
+/// Tap detection settings of the [`ADXL345`]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TapConfig {
+    /// Acceleration threshold in `g`
+    ///
+    /// Range --> (0.0 - 16.0)g
+    pub threshold: f32,
+    /// Maximum time above the threshold to count as a tap
+    ///
+    /// Range --> (0 - 159)ms
+    pub duration: Duration,
+    /// Wait after a tap before the double-tap window starts, zero disables double-taps
+    ///
+    /// Range --> (0 - 318)ms
+    pub latency: Duration,
+    /// Time window in which a second tap counts as a double-tap
+    ///
+    /// Range --> (0 - 318)ms
+    pub window: Duration,
+    /// Detect taps on the x, y and z axes
+    pub axes: [bool; 3],
+}
+
+impl Default for TapConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 3.0,
+            duration: Duration::from_millis(10),
+            latency: Duration::from_millis(20),
+            window: Duration::from_millis(200),
+            axes: [true, true, true],
+        }
+    }
+}
+
+/// Free-fall detection settings of the [`ADXL345`]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FreeFallConfig {
+    /// All axes should read below this acceleration in `g`
+    ///
+    /// Range --> (0.0 - 16.0)g, 0.3 - 0.6g recommended
+    pub threshold: f32,
+    /// For at least this long
+    ///
+    /// Range --> (0 - 1275)ms, 100 - 350ms recommended
+    pub time: Duration,
+}
+
+impl Default for FreeFallConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 0.4,
+            time: Duration::from_millis(150),
+        }
+    }
+}
+
+/// Interrupt events latched by the [`ADXL345`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Events {
+    /// New data is available
+    pub data_ready: bool,
+    /// A single tap was detected
+    pub single_tap: bool,
+    /// A double tap was detected
+    pub double_tap: bool,
+    /// Free fall was detected
+    pub free_fall: bool,
+}
+
+/// An acceleration reading in `g`
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Acceleration {
+    /// x axis
+    pub x: f32,
+    /// y axis
+    pub y: f32,
+    /// z axis
+    pub z: f32,
+}
+
+impl Acceleration {
+    /// Pitch angle in degrees, rotation around the y axis
+    ///
+    /// Range --> (-90.0 - 90.0)
+    pub fn pitch(&self) -> f32 {
+        (-self.x)
+            .atan2((self.y * self.y + self.z * self.z).sqrt())
+            .to_degrees()
+    }
+
+    /// Roll angle in degrees, rotation around the x axis
+    ///
+    /// Range --> (-180.0 - 180.0)
+    pub fn roll(&self) -> f32 {
+        self.y.atan2(self.z).to_degrees()
+    }
+}
+
+// Convert `value` to register steps of `step` each, saturating at 255
+fn steps(value: f32, step: f32) -> u8 {
+    (value / step).round().clamp(0.0, 255.0) as u8
+}
+
+/// A robot-hat ADXL345 accelerometer
+#[derive(Debug)]
+pub struct ADXL345 {
+    bus: I2c,
+}
+
+impl ADXL345 {
+    /// Create an ADXL345 on the robot-hat I2C bus at address `0x53`, measuring at
+    /// [`Range::G2`] and [`DataRate::Hz100`]
+    pub fn new() -> Result<Self> {
+        let bus = open_i2c(ADDR).context("I2C INIT FAILED")?;
+        let mut adxl = Self { bus };
+
+        let id = adxl.read_reg(REG_DEVID)?;
+        if id != DEVICE_ID {
+            bail!(
+                "ADXL345 device id should be {:#04x}, but read {:#04x}",
+                DEVICE_ID,
+                id
+            )
+        }
+
+        adxl.set_range(Range::default())?;
+        adxl.set_data_rate(DataRate::default())?;
+        adxl.write_reg(REG_INT_ENABLE, 0)?;
+        adxl.write_reg(REG_POWER_CTL, MEASURE)?;
+
+        Ok(adxl)
+    }
+
+    /// Set the measurement [`Range`]
+    pub fn set_range(&mut self, range: Range) -> Result<()> {
+        let bits = match range {
+            Range::G2 => 0b00,
+            Range::G4 => 0b01,
+            Range::G8 => 0b10,
+            Range::G16 => 0b11,
+        };
+        self.write_reg(REG_DATA_FORMAT, FULL_RES | bits)
+    }
+
+    /// Set the output [`DataRate`]
+    pub fn set_data_rate(&mut self, rate: DataRate) -> Result<()> {
+        self.write_reg(REG_BW_RATE, rate.code())
+    }
+
+    /// Read the acceleration on all 3 axes
+    pub fn read(&mut self) -> Result<Acceleration> {
+        let mut data = [0u8; 6];
+        self.bus
+            .block_read(REG_DATAX0, &mut data)
+            .context("ADXL345 DATA READ FAILED")?;
+        let axis = |i: usize| i16::from_le_bytes([data[i], data[i + 1]]) as f32 * SCALE;
+
+        Ok(Acceleration {
+            x: axis(0),
+            y: axis(2),
+            z: axis(4),
+        })
+    }
+
+    /// Read the pitch and roll angles in degrees
+    pub fn read_tilt(&mut self) -> Result<(f32, f32)> {
+        let acceleration = self.read()?;
+
+        Ok((acceleration.pitch(), acceleration.roll()))
+    }
+
+    /// Enable single and double tap detection, routed to `pin`
+    pub fn enable_tap(&mut self, config: TapConfig, pin: IntPin) -> Result<()> {
+        let axes = config
+            .axes
+            .iter()
+            .rev()
+            .enumerate()
+            .fold(0, |bits, (i, &on)| bits | (on as u8) << i);
+
+        self.write_reg(REG_THRESH_TAP, steps(config.threshold, 0.0625))?;
+        self.write_reg(REG_DUR, steps(config.duration.as_secs_f32(), 0.000625))?;
+        self.write_reg(REG_LATENT, steps(config.latency.as_secs_f32(), 0.00125))?;
+        self.write_reg(REG_WINDOW, steps(config.window.as_secs_f32(), 0.00125))?;
+        self.write_reg(REG_TAP_AXES, axes)?;
+        self.enable_interrupts(INT_SINGLE_TAP | INT_DOUBLE_TAP, pin)
+    }
+
+    /// Enable free-fall detection, routed to `pin`
+    pub fn enable_free_fall(&mut self, config: FreeFallConfig, pin: IntPin) -> Result<()> {
+        self.write_reg(REG_THRESH_FF, steps(config.threshold, 0.0625))?;
+        self.write_reg(REG_TIME_FF, steps(config.time.as_secs_f32(), 0.005))?;
+        self.enable_interrupts(INT_FREE_FALL, pin)
+    }
+
+    /// Disable all interrupts
+    pub fn disable_interrupts(&mut self) -> Result<()> {
+        self.write_reg(REG_INT_ENABLE, 0)
+    }
+
+    /// Read and clear the latched interrupt [`Events`]
+    pub fn read_events(&mut self) -> Result<Events> {
+        let source = self.read_reg(REG_INT_SOURCE)?;
+
+        Ok(Events {
+            data_ready: source & INT_DATA_READY != 0,
+            single_tap: source & INT_SINGLE_TAP != 0,
+            double_tap: source & INT_DOUBLE_TAP != 0,
+            free_fall: source & INT_FREE_FALL != 0,
+        })
+    }
+
+    fn enable_interrupts(&mut self, bits: u8, pin: IntPin) -> Result<()> {
+        let map = self.read_reg(REG_INT_MAP)?;
+        let map = match pin {
+            IntPin::Int1 => map & !bits,
+            IntPin::Int2 => map | bits,
+        };
+        self.write_reg(REG_INT_MAP, map)?;
+
+        let enabled = self.read_reg(REG_INT_ENABLE)?;
+        self.write_reg(REG_INT_ENABLE, enabled | bits)
+    }
+
+    fn read_reg(&mut self, reg: u8) -> Result<u8> {
+        self.bus
+            .smbus_read_byte(reg)
+            .with_context(|| format!("ADXL345 READ {:#04x} FAILED", reg))
+    }
+
+    fn write_reg(&mut self, reg: u8, value: u8) -> Result<()> {
+        self.bus
+            .smbus_write_byte(reg, value)
+            .with_context(|| format!("ADXL345 WRITE {:#04x} FAILED", reg))
+    }
+}
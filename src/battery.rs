@@ -0,0 +1,165 @@
+//! Battery Module
+
+use anyhow::{Context, Result};
+
+use crate::{adc::ADC, motor::Motors, pin::PinType, utils::map_range};
+
+// Battery Constants
+const BATTERY_PIN: PinType = PinType::A4;
+const DIVIDER_RATIO: f32 = 3.0;
+const CUTOFF: f32 = 6.6;
+const HYSTERESIS: f32 = 0.2;
+
+// (pack voltage, percentage) discharge curve of a 2S Li-ion pack
+const DISCHARGE_CURVE: [(f32, f32); 10] = [
+    (6.0, 0.0),
+    (6.8, 5.0),
+    (7.0, 10.0),
+    (7.2, 20.0),
+    (7.4, 35.0),
+    (7.6, 50.0),
+    (7.8, 65.0),
+    (8.0, 80.0),
+    (8.2, 90.0),
+    (8.4, 100.0),
+];
+
+/// Estimate the charge percentage of a 2S Li-ion pack from its voltage
+///
+/// Range --> (0.0 - 100.0)%
+pub fn percentage(voltage: f32) -> f32 {
+    let (first, last) = (
+        DISCHARGE_CURVE[0],
+        DISCHARGE_CURVE[DISCHARGE_CURVE.len() - 1],
+    );
+    if voltage <= first.0 {
+        return first.1;
+    }
+    if voltage >= last.0 {
+        return last.1;
+    }
+
+    DISCHARGE_CURVE
+        .windows(2)
+        .find(|w| voltage <= w[1].0)
+        .map(|w| map_range(voltage, (w[0].0, w[1].0), (w[0].1, w[1].1)))
+        .unwrap_or(last.1)
+}
+
+/// A battery reading returned by [`Battery::update`]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct BatteryStatus {
+    /// Pack voltage in `V`
+    pub voltage: f32,
+    /// Estimated charge in `%`
+    pub percentage: f32,
+    /// Whether the pack is below the cutoff
+    pub low: bool,
+}
+
+/// A robot-hat battery monitor
+pub struct Battery {
+    adc: ADC,
+    ratio: f32,
+    cutoff: f32,
+    hysteresis: f32,
+    low: bool,
+    on_low: Option<Box<dyn FnMut(BatteryStatus) + Send>>,
+}
+
+impl Battery {
+    /// Create a battery monitor using the following config as per robot-hat (Python)
+    ///
+    /// Battery is read on ADC pin `A4` through a `3:1` voltage divider
+    pub fn new() -> Result<Self> {
+        Self::with_divider(BATTERY_PIN, DIVIDER_RATIO)
+    }
+
+    /// Create a battery monitor on ADC pin with [`PinType`] *(A0-A7)* using a divider `ratio`
+    pub fn with_divider(adc_pin: PinType, ratio: f32) -> Result<Self> {
+        let adc = ADC::new(adc_pin).context("BATTERY ADC INIT FAILED")?;
+
+        Ok(Self {
+            adc,
+            ratio,
+            cutoff: CUTOFF,
+            hysteresis: HYSTERESIS,
+            low: false,
+            on_low: None,
+        })
+    }
+
+    /// Set the low-battery cutoff voltage and the hysteresis needed to recover from it
+    /// `(default: 6.6V, 0.2V)`
+    pub fn set_cutoff(&mut self, cutoff: f32, hysteresis: f32) {
+        self.cutoff = cutoff;
+        self.hysteresis = hysteresis.abs();
+    }
+
+    /// Call `callback` from [`Battery::update`] when the voltage drops below the cutoff
+    pub fn on_low<F>(&mut self, callback: F)
+    where
+        F: FnMut(BatteryStatus) + Send + 'static,
+    {
+        self.on_low = Some(Box::new(callback));
+    }
+
+    /// Read the battery pack voltage
+    pub fn read_voltage(&mut self) -> Result<f32> {
+        let voltage = self
+            .adc
+            .read_voltage()
+            .context("Reading battery voltage failed")?;
+
+        Ok(voltage * self.ratio)
+    }
+
+    /// Read the estimated battery charge
+    ///
+    /// Range --> (0.0 - 100.0)%
+    pub fn read_percentage(&mut self) -> Result<f32> {
+        Ok(percentage(self.read_voltage()?))
+    }
+
+    /// Read the battery and run the low-battery actions when the cutoff is crossed
+    ///
+    /// Call this periodically, e.g. from the control loop.
+    pub fn update(&mut self) -> Result<BatteryStatus> {
+        self.update_with(None)
+    }
+
+    /// Same as [`Battery::update`], also stopping `motors` on every call while the battery is low
+    pub fn update_and_stop(&mut self, motors: &mut Motors) -> Result<BatteryStatus> {
+        self.update_with(Some(motors))
+    }
+
+    fn update_with(&mut self, motors: Option<&mut Motors>) -> Result<BatteryStatus> {
+        let voltage = self.read_voltage()?;
+        let was_low = self.low;
+        if voltage < self.cutoff {
+            self.low = true;
+        } else if voltage > self.cutoff + self.hysteresis {
+            self.low = false;
+        }
+
+        let status = BatteryStatus {
+            voltage,
+            percentage: percentage(voltage),
+            low: self.low,
+        };
+
+        // Keep the motors stopped for as long as the battery is low
+        if self.low {
+            if let Some(motors) = motors {
+                motors.stop();
+            }
+        }
+        if self.low && !was_low {
+            if let Some(callback) = &mut self.on_low {
+                callback(status);
+            }
+        }
+
+        Ok(status)
+    }
+}
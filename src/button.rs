@@ -0,0 +1,243 @@
+//! Button Module
+
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread::spawn;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use rppal::gpio::{InputPin, Trigger};
+
+use crate::pin::{PinType, RHPin};
+
+// Button Constants
+const DEBOUNCE: Duration = Duration::from_millis(20);
+const DOUBLE_CLICK: Duration = Duration::from_millis(300);
+const LONG_PRESS: Duration = Duration::from_millis(800);
+
+/// An event reported by a [`Button`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ButtonEvent {
+    /// The button was pressed
+    Pressed,
+    /// The button was released
+    Released,
+    /// The button was pressed and released once
+    Click,
+    /// The button was clicked twice in quick succession
+    DoubleClick,
+    /// The button was held down for the long-press time
+    LongPress,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Timing {
+    double_click: Duration,
+    long_press: Duration,
+}
+
+/// A robot-hat button, pressed when the pin is pulled low
+pub struct Button {
+    pin: InputPin,
+    debounce: Duration,
+    timing: Timing,
+}
+
+impl Button {
+    /// Create a button using a pin with [`PinType`], usually [`PinType::SW`] or [`PinType::User`]
+    pub fn new(pin: PinType) -> Result<Self> {
+        let pin = RHPin::new(pin)
+            .with_context(|| format!("Creating button pin using {:?} failed", pin))?;
+
+        Ok(Self {
+            pin: pin.gpio_pin.into_input_pullup(),
+            debounce: DEBOUNCE,
+            timing: Timing {
+                double_click: DOUBLE_CLICK,
+                long_press: LONG_PRESS,
+            },
+        })
+    }
+
+    /// Set the debounce time `(default: 20ms)`
+    ///
+    /// Takes effect on the next call to [`Button::on_event`].
+    pub fn set_debounce(&mut self, debounce: Duration) {
+        self.debounce = debounce;
+    }
+
+    /// Set the longest gap between two clicks to count as a double-click `(default: 300ms)`
+    ///
+    /// Takes effect on the next call to [`Button::on_event`].
+    pub fn set_double_click(&mut self, double_click: Duration) {
+        self.timing.double_click = double_click;
+    }
+
+    /// Set how long the button has to be held to count as a long-press `(default: 800ms)`
+    ///
+    /// Takes effect on the next call to [`Button::on_event`].
+    pub fn set_long_press(&mut self, long_press: Duration) {
+        self.timing.long_press = long_press;
+    }
+
+    /// Check whether the button is currently pressed
+    pub fn is_pressed(&self) -> bool {
+        self.pin.is_low()
+    }
+
+    /// Call `callback` with every [`ButtonEvent`], replacing any previous callback
+    ///
+    /// Edges are detected with GPIO interrupts; gestures are recognised on a background thread.
+    pub fn on_event<F>(&mut self, mut callback: F) -> Result<()>
+    where
+        F: FnMut(ButtonEvent) + Send + 'static,
+    {
+        let (tx, rx) = channel();
+        // The receiving thread ends when the interrupt, and with it `tx`, is cleared
+        self.pin
+            .set_async_interrupt(Trigger::Both, Some(self.debounce), move |event| {
+                let _ = tx.send(event.trigger == Trigger::FallingEdge);
+            })
+            .context("Setting button interrupt failed")?;
+
+        let timing = self.timing;
+        spawn(move || recognise(rx, timing, &mut callback));
+
+        Ok(())
+    }
+
+    /// Receive every [`ButtonEvent`], replacing any previous callback
+    pub fn events(&mut self) -> Result<Receiver<ButtonEvent>> {
+        let (tx, rx) = channel();
+        self.on_event(move |event| {
+            let _ = tx.send(event);
+        })?;
+
+        Ok(rx)
+    }
+
+    /// Stop reporting events
+    pub fn clear(&mut self) -> Result<()> {
+        self.pin
+            .clear_async_interrupt()
+            .context("Clearing button interrupt failed")
+    }
+}
+
+// Turn press (`true`) and release (`false`) edges into gestures
+fn recognise<F>(edges: Receiver<bool>, timing: Timing, callback: &mut F)
+where
+    F: FnMut(ButtonEvent),
+{
+    let mut pressed_at: Option<Instant> = None;
+    let mut long_pressed = false;
+    let mut clicked_at: Option<Instant> = None;
+
+    loop {
+        let deadline = match (pressed_at, clicked_at) {
+            (Some(pressed), _) if !long_pressed => Some(pressed + timing.long_press),
+            (None, Some(clicked)) => Some(clicked + timing.double_click),
+            _ => None,
+        };
+        let edge = match deadline {
+            Some(deadline) => {
+                edges.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            None => edges.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match edge {
+            Ok(true) => {
+                callback(ButtonEvent::Pressed);
+                pressed_at = Some(Instant::now());
+                long_pressed = false;
+            }
+            Ok(false) => {
+                callback(ButtonEvent::Released);
+                if pressed_at.take().is_some() && !long_pressed {
+                    if clicked_at.take().is_some() {
+                        callback(ButtonEvent::DoubleClick);
+                    } else {
+                        clicked_at = Some(Instant::now());
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if pressed_at.is_some() {
+                    // A click pending before this press is not part of the long-press
+                    if clicked_at.take().is_some() {
+                        callback(ButtonEvent::Click);
+                    }
+                    callback(ButtonEvent::LongPress);
+                    long_pressed = true;
+                } else if clicked_at.take().is_some() {
+                    callback(ButtonEvent::Click);
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::Sender;
+    use std::thread::sleep;
+
+    use super::ButtonEvent::*;
+    use super::*;
+
+    const TIMING: Timing = Timing {
+        double_click: Duration::from_millis(50),
+        long_press: Duration::from_millis(100),
+    };
+
+    // Run `recognise` on edges sent by `send`, collecting every event
+    fn events(send: impl FnOnce(&Sender<bool>) + Send + 'static) -> Vec<ButtonEvent> {
+        let (tx, rx) = channel();
+        spawn(move || send(&tx));
+        let mut events = Vec::new();
+        recognise(rx, TIMING, &mut |event| events.push(event));
+
+        events
+    }
+
+    #[test]
+    fn reports_single_click() {
+        let events = events(|tx| {
+            tx.send(true).unwrap();
+            tx.send(false).unwrap();
+            sleep(TIMING.double_click * 3);
+        });
+
+        assert_eq!(events, [Pressed, Released, Click]);
+    }
+
+    #[test]
+    fn reports_double_click() {
+        let events = events(|tx| {
+            for _ in 0..2 {
+                tx.send(true).unwrap();
+                tx.send(false).unwrap();
+            }
+            sleep(TIMING.double_click * 3);
+        });
+
+        assert_eq!(events, [Pressed, Released, Pressed, Released, DoubleClick]);
+    }
+
+    #[test]
+    fn keeps_click_before_long_press() {
+        let events = events(|tx| {
+            tx.send(true).unwrap();
+            tx.send(false).unwrap();
+            tx.send(true).unwrap();
+            sleep(TIMING.long_press * 3);
+            tx.send(false).unwrap();
+        });
+
+        assert_eq!(
+            events,
+            [Pressed, Released, Pressed, Click, LongPress, Released]
+        );
+    }
+}
@@ -0,0 +1,310 @@
+//! Buzzer Module
+
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use rppal::gpio::OutputPin;
+
+use crate::{
+    pin::{PinType, RHPin},
+    pwm::PWM,
+};
+
+// Buzzer Constants
+const DUTY_CYCLE: u8 = 50;
+const A4_FREQ: f32 = 440.0;
+// Fraction of each melody note that is sounded, the rest separates repeated notes
+const ARTICULATION: f32 = 0.9;
+
+/// Get the frequency in `Hz` of a note name like `C4`, `A#5` or `Bb3`
+pub fn note_freq(name: &str) -> Result<f32> {
+    let mut chars = name.trim().chars();
+    let semitone: i32 = match chars.next().map(|c| c.to_ascii_uppercase()) {
+        Some('C') => 0,
+        Some('D') => 2,
+        Some('E') => 4,
+        Some('F') => 5,
+        Some('G') => 7,
+        Some('A') => 9,
+        Some('B') => 11,
+        _ => bail!("Invalid note name {:?}", name),
+    };
+    let rest = chars.as_str();
+    let (semitone, octave) = if let Some(octave) = rest.strip_prefix('#') {
+        (semitone + 1, octave)
+    } else if let Some(octave) = rest.strip_prefix('b') {
+        (semitone - 1, octave)
+    } else {
+        (semitone, rest)
+    };
+    let octave: i32 = octave
+        .parse()
+        .with_context(|| format!("Invalid octave in note name {:?}", name))?;
+
+    // MIDI note number, A4 = 69
+    let midi = (octave + 1) * 12 + semitone;
+
+    Ok(A4_FREQ * 2_f32.powf((midi - 69) as f32 / 12.0))
+}
+
+/// A single tone of a [`Melody`]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tone {
+    /// Frequency in `Hz`, `None` for a rest
+    pub freq: Option<f32>,
+    /// How long the tone lasts
+    pub duration: Duration,
+}
+
+/// A sequence of [`Tone`]s played at a tempo
+#[derive(Clone, Debug, PartialEq)]
+pub struct Melody {
+    tempo: f32,
+    tones: Vec<Tone>,
+}
+
+impl Melody {
+    /// Create an empty melody at `tempo` beats per minute
+    pub fn new(tempo: f32) -> Self {
+        Self {
+            tempo,
+            tones: Vec::new(),
+        }
+    }
+
+    /// Create a melody at `tempo` beats per minute from `(note name, beats)` pairs
+    ///
+    /// Use `R` as the note name for a rest.
+    pub fn from_notes(tempo: f32, notes: &[(&str, f32)]) -> Result<Self> {
+        let mut melody = Self::new(tempo);
+        for &(name, beats) in notes {
+            if name.eq_ignore_ascii_case("R") {
+                melody.rest(beats);
+            } else {
+                melody.note(name, beats)?;
+            }
+        }
+
+        Ok(melody)
+    }
+
+    /// Create a melody at `tempo` beats per minute from already timed [`Tone`]s
+    ///
+    /// The tempo only applies to notes and rests appended afterwards.
+    pub fn from_tones(tempo: f32, tones: Vec<Tone>) -> Self {
+        Self { tempo, tones }
+    }
+
+    /// Append a note name like `C4` lasting `beats`
+    pub fn note(&mut self, name: &str, beats: f32) -> Result<()> {
+        let freq = note_freq(name)?;
+        self.push(Some(freq), beats);
+
+        Ok(())
+    }
+
+    /// Append a rest lasting `beats`
+    pub fn rest(&mut self, beats: f32) {
+        self.push(None, beats);
+    }
+
+    /// Get the timed [`Tone`]s of the melody
+    pub fn tones(&self) -> &[Tone] {
+        &self.tones
+    }
+
+    fn push(&mut self, freq: Option<f32>, beats: f32) {
+        let duration = Duration::from_secs_f32((60.0 / self.tempo.max(1.0) * beats).max(0.0));
+        self.tones.push(Tone { freq, duration });
+    }
+}
+
+enum Output {
+    Active(OutputPin),
+    Passive(PWM),
+}
+
+/// A robot-hat Buzzer
+pub struct Buzzer {
+    output: Output,
+}
+
+impl Buzzer {
+    /// Create an active buzzer on a digital pin with [`PinType`]  *(D0-D16)*
+    ///
+    /// Active buzzers sound at a fixed pitch, so tone frequencies are ignored.
+    pub fn active(pin: PinType) -> Result<Self> {
+        if !pin.is_digital_pin() {
+            bail!("pin should be one of PinType::D0-D16, but passed {:?}", pin)
+        }
+        let pin = RHPin::new(pin)
+            .with_context(|| format!("Creating buzzer pin using {:?} failed", pin))?;
+        let output = Output::Active(pin.gpio_pin.into_output_low());
+
+        Ok(Self { output })
+    }
+
+    /// Create a passive buzzer on a pwm pin with [`PinType`]  *(P0-P13)*
+    ///
+    /// Every tone retunes the timer shared by the pin's group of four channels
+    /// (P0-P3, P4-P7, P8-P11, P12-P13), so pick a group without servos or motors,
+    /// e.g. not P12/P13 which drive [`Motors`](crate::motor::Motors).
+    pub fn passive(pin: PinType) -> Result<Self> {
+        let mut pwm = PWM::new(pin).context("BUZZER PWM INIT FAILED")?;
+        pwm.pulse_width_percent(0)?;
+
+        Ok(Self {
+            output: Output::Passive(pwm),
+        })
+    }
+
+    /// Start sounding at `freq` Hz until [`Buzzer::off`] is called
+    pub fn on(&mut self, freq: f32) -> Result<()> {
+        match &mut self.output {
+            Output::Active(pin) => pin.set_high(),
+            Output::Passive(pwm) => {
+                let freq = freq.round().clamp(1.0, u16::MAX as f32) as u16;
+                pwm.freq(freq)?;
+                pwm.pulse_width_percent(DUTY_CYCLE)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stop sounding
+    pub fn off(&mut self) -> Result<()> {
+        match &mut self.output {
+            Output::Active(pin) => pin.set_low(),
+            Output::Passive(pwm) => pwm.pulse_width_percent(0)?,
+        }
+
+        Ok(())
+    }
+
+    /// Sound at `freq` Hz for `duration`
+    pub fn tone(&mut self, freq: f32, duration: Duration) -> Result<()> {
+        self.on(freq)?;
+        sleep(duration);
+        self.off()
+    }
+
+    /// Play a [`Melody`], blocking until it finishes
+    pub fn play(&mut self, melody: &Melody) -> Result<()> {
+        for tone in melody.tones() {
+            self.play_tone(tone, |duration| {
+                sleep(duration);
+                true
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Play a single [`Tone`], waiting with `wait` which returns `false` to interrupt playback
+    fn play_tone<F>(&mut self, tone: &Tone, mut wait: F) -> Result<bool>
+    where
+        F: FnMut(Duration) -> bool,
+    {
+        let Some(freq) = tone.freq else {
+            return Ok(wait(tone.duration));
+        };
+
+        let sounding = tone.duration.mul_f32(ARTICULATION);
+        self.on(freq)?;
+        let finished = wait(sounding);
+        self.off()?;
+
+        Ok(finished && wait(tone.duration - sounding))
+    }
+}
+
+enum Command {
+    Play(Melody),
+    Stop,
+}
+
+/// Non-blocking [`Melody`] playback on a background thread
+pub struct BuzzerPlayer {
+    commands: Option<Sender<Command>>,
+    handle: Option<JoinHandle<Buzzer>>,
+}
+
+impl BuzzerPlayer {
+    /// Start a background player owning `buzzer`
+    pub fn new(mut buzzer: Buzzer) -> Self {
+        let (commands, rx) = channel();
+
+        let handle = spawn(move || {
+            let mut next = rx.recv().ok();
+            while let Some(command) = next.take() {
+                let Command::Play(melody) = command else {
+                    next = rx.recv().ok();
+                    continue;
+                };
+
+                for tone in melody.tones() {
+                    let played =
+                        buzzer.play_tone(tone, |duration| match rx.recv_timeout(duration) {
+                            Ok(command) => {
+                                next = Some(command);
+                                false
+                            }
+                            Err(RecvTimeoutError::Timeout) => true,
+                            Err(RecvTimeoutError::Disconnected) => false,
+                        });
+                    if !matches!(played, Ok(true)) {
+                        break;
+                    }
+                }
+                let _ = buzzer.off();
+
+                if next.is_none() {
+                    next = rx.recv().ok();
+                }
+            }
+
+            buzzer
+        });
+
+        Self {
+            commands: Some(commands),
+            handle: Some(handle),
+        }
+    }
+
+    /// Start playing `melody`, replacing any melody currently playing
+    pub fn play(&self, melody: Melody) {
+        self.send(Command::Play(melody));
+    }
+
+    /// Stop the melody currently playing
+    pub fn stop(&self) {
+        self.send(Command::Stop);
+    }
+
+    /// Stop playback and return the [`Buzzer`]
+    pub fn into_inner(mut self) -> Result<Buzzer> {
+        self.shutdown().context("Buzzer player thread panicked")
+    }
+
+    fn send(&self, command: Command) {
+        if let Some(commands) = &self.commands {
+            let _ = commands.send(command);
+        }
+    }
+
+    fn shutdown(&mut self) -> Option<Buzzer> {
+        // Dropping the sender ends the player thread
+        self.commands.take();
+        self.handle.take().and_then(|handle| handle.join().ok())
+    }
+}
+
+impl Drop for BuzzerPlayer {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
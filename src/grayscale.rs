@@ -1,50 +1,124 @@
 //! Grayscale module implementation
 
-use anyhow::{Context, Result};
+use std::time::{Duration, Instant};
 
-use crate::adc::ADC;
+use anyhow::{bail, Context, Result};
+
+use crate::adc::ADCBus;
+use crate::motor::Motors;
 use crate::pin::PinType;
+use crate::settings::Settings;
+
+const GRAYSCALE_REF: u16 = 1000;
+const GRAYSCALE_BLACK: u16 = 0;
+const GRAYSCALE_WHITE: u16 = 4095;
+const CLIFF_REF: u16 = 200;
 
-const GRAYSCALE_REFS: [u16; 3] = [1000, 1000, 1000];
+/// Per-channel black and white levels and line thresholds of a [`Grayscale`] sensor
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct GrayscaleCalibration<const N: usize = 3> {
+    /// Analog values read over a black surface
+    pub black: [u16; N],
+    /// Analog values read over a white surface
+    pub white: [u16; N],
+    /// Reference values used by [`Grayscale::read_status`]
+    pub refs: [u16; N],
+}
 
-/// 3 channel Grayscale sensor
-pub struct Grayscale {
-    channels: [ADC; 3],
-    refs: [u16; 3],
+impl<const N: usize> Default for GrayscaleCalibration<N> {
+    fn default() -> Self {
+        Self {
+            black: [GRAYSCALE_BLACK; N],
+            white: [GRAYSCALE_WHITE; N],
+            refs: [GRAYSCALE_REF; N],
+        }
+    }
+}
+
+impl<const N: usize> GrayscaleCalibration<N> {
+    /// Create a calibration from black and white levels, with reference values halfway between them
+    pub fn from_levels(black: [u16; N], white: [u16; N]) -> Self {
+        let mut refs = [0; N];
+        for (i, r) in refs.iter_mut().enumerate() {
+            *r = ((black[i] as u32 + white[i] as u32) / 2) as u16;
+        }
+
+        Self { black, white, refs }
+    }
+
+    /// Store the calibration in `settings`
+    pub fn save(&self, settings: &mut Settings) {
+        for i in 0..N {
+            settings.set(&format!("grayscale_{}_black", i), self.black[i]);
+            settings.set(&format!("grayscale_{}_white", i), self.white[i]);
+            settings.set(&format!("grayscale_{}_ref", i), self.refs[i]);
+        }
+    }
+
+    /// Load a calibration from `settings`, if one was saved
+    pub fn load(settings: &Settings) -> Option<Self> {
+        let mut calibration = Self::default();
+        for i in 0..N {
+            calibration.black[i] = settings.get(&format!("grayscale_{}_black", i))?;
+            calibration.white[i] = settings.get(&format!("grayscale_{}_white", i))?;
+            calibration.refs[i] = settings.get(&format!("grayscale_{}_ref", i))?;
+        }
+
+        Some(calibration)
+    }
+}
+
+/// `N` channel Grayscale sensor, 3 channels by default
+pub struct Grayscale<const N: usize = 3> {
+    bus: ADCBus,
+    channels: [PinType; N],
+    calibration: GrayscaleCalibration<N>,
+    cliff_refs: [u16; N],
 }
 
 impl Grayscale {
-    /// Create a Grayscale sensor with 3 default channels using 3 [`ADC`]` pins with [`PinType`] *(A0-A7)*
+    /// Create a Grayscale sensor with 3 default channels using 3 ADC pins with [`PinType`] *(A0-A7)*
     pub fn new(left: PinType, middle: PinType, right: PinType) -> Result<Self> {
-        let left = ADC::new(left).context("Creating ADC Pin failed")?;
-        let middle = ADC::new(middle).context("Creating ADC Pin failed")?;
-        let right = ADC::new(right).context("Creating ADC Pin failed")?;
+        Self::with_channels([left, middle, right])
+    }
+}
 
-        let channels = [left, middle, right];
+impl<const N: usize> Grayscale<N> {
+    /// Create a Grayscale sensor using `N` ADC pins with [`PinType`] *(A0-A7)*, ordered from left to right
+    pub fn with_channels(channels: [PinType; N]) -> Result<Self> {
+        if N == 0 {
+            bail!("Grayscale sensor needs at least one channel")
+        }
+        let bus = ADCBus::new().context("Creating ADC bus failed")?;
 
         Ok(Grayscale {
+            bus,
             channels,
-            refs: GRAYSCALE_REFS,
+            calibration: GrayscaleCalibration::default(),
+            cliff_refs: [CLIFF_REF; N],
         })
     }
 
     /// Set reference analog values for the channels `(default: 1000)`
-    pub fn set_reference_values(mut self, refs: [u16; 3]) {
-        self.refs = refs;
+    pub fn set_reference_values(&mut self, refs: [u16; N]) {
+        self.calibration.refs = refs;
     }
 
-    /// Read all 3 ADC channel values
-    pub fn read_values(mut self) -> Result<[u16; 3]> {
-        let mut values = [0, 0, 0];
-        values[0] = self.channels[0]
-            .read_value()
-            .context("Reading ADC value failed")?;
-        values[1] = self.channels[1]
-            .read_value()
-            .context("Reading ADC value failed")?;
-        values[2] = self.channels[2]
-            .read_value()
+    /// Set the analog values read over black and white surfaces for the channels
+    /// `(default: 0 and 4095)`
+    pub fn set_levels(&mut self, black: [u16; N], white: [u16; N]) {
+        self.calibration.black = black;
+        self.calibration.white = white;
+    }
+
+    /// Read all `N` ADC channel values
+    pub fn read_values(&mut self) -> Result<[u16; N]> {
+        let snapshot = self
+            .bus
+            .read_values(&self.channels)
             .context("Reading ADC value failed")?;
+        let mut values = [0; N];
+        values.copy_from_slice(&snapshot.values);
 
         Ok(values)
     }
@@ -52,14 +126,116 @@ impl Grayscale {
     /// Read Grayscale sensor statuses
     ///
     /// Array of line status, `true` for `white`, `false` for `black`
-    pub fn read_status(self) -> Result<[bool; 3]> {
-        let refs = self.refs;
+    pub fn read_status(&mut self) -> Result<[bool; N]> {
+        let refs = self.calibration.refs;
         let values = self.read_values()?;
-        let mut status = [false, false, false];
+        let mut status = [false; N];
         for (i, v) in values.into_iter().enumerate() {
             status[i] = v > refs[i];
         }
 
         Ok(status)
     }
+
+    /// Read Grayscale sensor values normalized between the black and white levels
+    ///
+    /// Range --> (0.0 - 1.0), `0.0` for `black`, `1.0` for `white`
+    pub fn read_normalized(&mut self) -> Result<[f32; N]> {
+        let values = self.read_values()?;
+        let mut normalized = [0.0; N];
+        for (i, v) in values.into_iter().enumerate() {
+            let black = self.calibration.black[i] as f32;
+            let white = self.calibration.white[i] as f32;
+            let span = (white - black).max(1.0);
+            normalized[i] = ((v as f32 - black) / span).clamp(0.0, 1.0);
+        }
+
+        Ok(normalized)
+    }
+
+    /// Set the black and white levels and reference values from a [`GrayscaleCalibration`]
+    pub fn set_calibration(&mut self, calibration: GrayscaleCalibration<N>) {
+        self.calibration = calibration;
+    }
+
+    /// Get the current [`GrayscaleCalibration`]
+    pub fn calibration(&self) -> GrayscaleCalibration<N> {
+        self.calibration
+    }
+
+    /// Read the channel values averaged over `samples` reads
+    ///
+    /// Use over a white surface and over a black line, then pass both to
+    /// [`GrayscaleCalibration::from_levels`].
+    pub fn sample_average(&mut self, samples: usize) -> Result<[u16; N]> {
+        let samples = samples.max(1);
+        let mut sums = [0u32; N];
+        for _ in 0..samples {
+            let values = self.read_values()?;
+            for (sum, v) in sums.iter_mut().zip(values) {
+                *sum += v as u32;
+            }
+        }
+
+        Ok(sums.map(|sum| (sum / samples as u32) as u16))
+    }
+
+    /// Calibrate by sampling continuously for `duration` while the sensor sweeps across
+    /// both the line and the background, e.g. while the robot rotates in place
+    ///
+    /// The lowest and highest values seen become the black and white levels, and the
+    /// calibration is applied to this sensor.
+    pub fn calibrate_sweep(&mut self, duration: Duration) -> Result<GrayscaleCalibration<N>> {
+        let start = Instant::now();
+        let mut black = [u16::MAX; N];
+        let mut white = [u16::MIN; N];
+        while start.elapsed() < duration {
+            let values = self.read_values()?;
+            for (i, v) in values.into_iter().enumerate() {
+                black[i] = black[i].min(v);
+                white[i] = white[i].max(v);
+            }
+        }
+
+        if black.iter().zip(white).any(|(&b, w)| b >= w) {
+            bail!(
+                "Calibration sweep saw no contrast: black {:?}, white {:?}",
+                black,
+                white
+            )
+        }
+        let calibration = GrayscaleCalibration::from_levels(black, white);
+        self.set_calibration(calibration);
+
+        Ok(calibration)
+    }
+
+    /// Set reference analog values for cliff detection `(default: 200)`
+    pub fn set_cliff_reference_values(&mut self, refs: [u16; N]) {
+        self.cliff_refs = refs;
+    }
+
+    /// Read Grayscale sensor cliff statuses
+    ///
+    /// Array of cliff status, `true` where the sensor sees a drop-off
+    pub fn read_cliff(&mut self) -> Result<[bool; N]> {
+        let refs = self.cliff_refs;
+        let values = self.read_values()?;
+        let mut cliff = [false; N];
+        for (i, v) in values.into_iter().enumerate() {
+            cliff[i] = v <= refs[i];
+        }
+
+        Ok(cliff)
+    }
+
+    /// Read Grayscale sensor cliff statuses and stop `motors` if any sensor sees a drop-off
+    pub fn stop_on_cliff(&mut self, motors: &mut Motors) -> Result<[bool; N]> {
+        let cliff = self.read_cliff()?;
+        if cliff.contains(&true) {
+            motors.stop();
+        }
+
+        Ok(cliff)
+    }
 }
@@ -0,0 +1,239 @@
+//! Onboard LED Module
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{spawn, JoinHandle};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use rppal::gpio::OutputPin;
+
+use crate::pin::{PinType, RHPin};
+
+// LED Constants
+const CODE_BLINK: Duration = Duration::from_millis(150);
+const CODE_PAUSE: Duration = Duration::from_secs(1);
+
+fn morse(c: char) -> Option<&'static str> {
+    let code = match c.to_ascii_uppercase() {
+        'A' => ".-",
+        'B' => "-...",
+        'C' => "-.-.",
+        'D' => "-..",
+        'E' => ".",
+        'F' => "..-.",
+        'G' => "--.",
+        'H' => "....",
+        'I' => "..",
+        'J' => ".---",
+        'K' => "-.-",
+        'L' => ".-..",
+        'M' => "--",
+        'N' => "-.",
+        'O' => "---",
+        'P' => ".--.",
+        'Q' => "--.-",
+        'R' => ".-.",
+        'S' => "...",
+        'T' => "-",
+        'U' => "..-",
+        'V' => "...-",
+        'W' => ".--",
+        'X' => "-..-",
+        'Y' => "-.--",
+        'Z' => "--..",
+        '0' => "-----",
+        '1' => ".----",
+        '2' => "..---",
+        '3' => "...--",
+        '4' => "....-",
+        '5' => ".....",
+        '6' => "-....",
+        '7' => "--...",
+        '8' => "---..",
+        '9' => "----.",
+        _ => return None,
+    };
+
+    Some(code)
+}
+
+/// A blink pattern played by [`Led::start_pattern`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pattern {
+    steps: Vec<(bool, Duration)>,
+    repeat: bool,
+}
+
+impl Pattern {
+    /// Create a pattern from `(on, duration)` steps, optionally repeating forever
+    pub fn new(steps: Vec<(bool, Duration)>, repeat: bool) -> Self {
+        Self { steps, repeat }
+    }
+
+    /// Blink forever, on for `on` and off for `off`
+    pub fn blink(on: Duration, off: Duration) -> Self {
+        Self::new(vec![(true, on), (false, off)], true)
+    }
+
+    /// Blink `count` times quickly followed by a pause, repeating forever
+    ///
+    /// e.g. `Pattern::code(3)` for an error status
+    pub fn code(count: usize) -> Self {
+        let mut steps = Vec::with_capacity(count * 2);
+        for _ in 0..count {
+            steps.extend([(true, CODE_BLINK), (false, CODE_BLINK)]);
+        }
+        steps.push((false, CODE_PAUSE));
+
+        Self::new(steps, true)
+    }
+
+    /// Spell `text` in Morse code, a dot lasting `unit`
+    ///
+    /// Supports letters, digits and spaces.
+    pub fn morse(text: &str, unit: Duration, repeat: bool) -> Result<Self> {
+        let mut steps = Vec::new();
+        for word in text.split_whitespace() {
+            if !steps.is_empty() {
+                // Word gap is 7 units
+                steps.push((false, unit * 7));
+            }
+            for (i, c) in word.chars().enumerate() {
+                let Some(code) = morse(c) else {
+                    bail!("Cannot spell {:?} in Morse code", c)
+                };
+                if i > 0 {
+                    // Letter gap is 3 units
+                    steps.push((false, unit * 3));
+                }
+                for (j, symbol) in code.chars().enumerate() {
+                    if j > 0 {
+                        steps.push((false, unit));
+                    }
+                    let length = if symbol == '-' { unit * 3 } else { unit };
+                    steps.push((true, length));
+                }
+            }
+        }
+        if repeat {
+            steps.push((false, unit * 7));
+        }
+
+        Ok(Self::new(steps, repeat))
+    }
+}
+
+/// The robot-hat onboard LED
+pub struct Led {
+    pin: Option<OutputPin>,
+    // Level of the pin, also kept up to date by the pattern thread
+    level: Arc<AtomicBool>,
+    pattern: Option<(Sender<()>, JoinHandle<OutputPin>)>,
+}
+
+impl Led {
+    /// Create the onboard LED using [`PinType::Led`]
+    pub fn new() -> Result<Self> {
+        let pin = RHPin::new(PinType::Led).context("Creating LED pin failed")?;
+
+        Ok(Self {
+            pin: Some(pin.gpio_pin.into_output_low()),
+            level: Arc::new(AtomicBool::new(false)),
+            pattern: None,
+        })
+    }
+
+    /// Switch the LED on, stopping any running pattern
+    pub fn on(&mut self) -> Result<()> {
+        self.set(true)
+    }
+
+    /// Switch the LED off, stopping any running pattern
+    pub fn off(&mut self) -> Result<()> {
+        self.set(false)
+    }
+
+    /// Toggle the LED, stopping any running pattern
+    pub fn toggle(&mut self) -> Result<()> {
+        // Read the level before stopping the pattern, which leaves the LED off
+        let on = self.is_on();
+        self.set(!on)
+    }
+
+    /// Check whether the LED is on, following any running pattern
+    pub fn is_on(&self) -> bool {
+        self.level.load(Ordering::Relaxed)
+    }
+
+    /// Blink forever on a background thread, on for `on` and off for `off`
+    pub fn blink(&mut self, on: Duration, off: Duration) -> Result<()> {
+        self.start_pattern(Pattern::blink(on, off))
+    }
+
+    /// Play a [`Pattern`] on a background thread until another state or pattern is set
+    pub fn start_pattern(&mut self, pattern: Pattern) -> Result<()> {
+        self.stop_pattern()?;
+        let mut pin = self.pin.take().context("LED pin is unavailable")?;
+        let level = Arc::clone(&self.level);
+        let (stop, stopped) = channel();
+
+        let handle = spawn(move || {
+            'play: loop {
+                for &(on, duration) in &pattern.steps {
+                    if on {
+                        pin.set_high();
+                    } else {
+                        pin.set_low();
+                    }
+                    level.store(on, Ordering::Relaxed);
+                    // Any message or a dropped sender stops the pattern
+                    if stopped.recv_timeout(duration) != Err(RecvTimeoutError::Timeout) {
+                        break 'play;
+                    }
+                }
+                if !pattern.repeat || pattern.steps.is_empty() {
+                    break;
+                }
+            }
+            pin.set_low();
+            level.store(false, Ordering::Relaxed);
+
+            pin
+        });
+        self.pattern = Some((stop, handle));
+
+        Ok(())
+    }
+
+    /// Stop the running pattern, if any, leaving the LED off
+    pub fn stop_pattern(&mut self) -> Result<()> {
+        if let Some((stop, handle)) = self.pattern.take() {
+            drop(stop);
+            let pin = handle.join().ok().context("LED pattern thread panicked")?;
+            self.pin = Some(pin);
+        }
+
+        Ok(())
+    }
+
+    fn set(&mut self, on: bool) -> Result<()> {
+        self.stop_pattern()?;
+        let pin = self.pin.as_mut().context("LED pin is unavailable")?;
+        if on {
+            pin.set_high();
+        } else {
+            pin.set_low();
+        }
+        self.level.store(on, Ordering::Relaxed);
+
+        Ok(())
+    }
+}
+
+impl Drop for Led {
+    fn drop(&mut self) {
+        let _ = self.stop_pattern();
+    }
+}
@@ -1,10 +1,22 @@
 //! The unofficial Rust implementation of [robot-hat Python](https://github.com/sunfounder/robot-hat) Library.
 
 pub mod adc;
+pub mod adxl345;
+pub mod battery;
+pub mod button;
+pub mod buzzer;
 pub mod grayscale;
+pub mod led;
+pub mod line;
 pub mod motor;
+pub mod music;
 pub mod pin;
 pub mod pwm;
+pub mod rgb_led;
+pub mod rtttl;
 pub mod servo;
+pub mod settings;
+pub mod speaker;
+pub mod tts;
 pub mod ultrasonic;
 pub mod utils;
@@ -0,0 +1,279 @@
+//! Line position estimation and line following
+
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+
+use crate::{grayscale::Grayscale, motor::Motors};
+
+// Line Constants
+const LINE_THRESHOLD: f32 = 0.5;
+const KP: f32 = 30.0;
+const KI: f32 = 0.0;
+const KD: f32 = 2.0;
+
+/// Position of the line under a [`Grayscale`] sensor
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LinePosition {
+    /// The line is seen at an offset from the centre
+    ///
+    /// Range --> (-1.0 - 1.0), `-1.0` under the leftmost channel, `1.0` under the rightmost
+    Offset(f32),
+    /// No channel sees the line
+    Lost,
+    /// Every channel sees the line, e.g. at a crossing
+    Intersection,
+}
+
+/// Estimates a continuous [`LinePosition`] from normalized grayscale values
+#[derive(Copy, Clone, Debug)]
+pub struct LineEstimator {
+    threshold: f32,
+}
+
+impl Default for LineEstimator {
+    fn default() -> Self {
+        Self {
+            threshold: LINE_THRESHOLD,
+        }
+    }
+}
+
+impl LineEstimator {
+    /// Create a line estimator with the default threshold
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how dark a channel has to be to see the line `(default: 0.5)`
+    ///
+    /// Range --> (0.0 - 1.0), darkness is `1.0 - normalized value`
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold.clamp(0.0, 1.0);
+    }
+
+    /// Estimate the line position from values returned by [`Grayscale::read_normalized`],
+    /// ordered from left to right
+    ///
+    /// Assumes a dark line on a light background.
+    pub fn estimate(&self, normalized: &[f32]) -> LinePosition {
+        let darkness: Vec<f32> = normalized
+            .iter()
+            .map(|v| (1.0 - v).clamp(0.0, 1.0))
+            .collect();
+        let on_line = darkness.iter().filter(|&&d| d >= self.threshold).count();
+
+        if on_line == 0 {
+            return LinePosition::Lost;
+        }
+        if on_line == darkness.len() && darkness.len() > 1 {
+            return LinePosition::Intersection;
+        }
+
+        let last = (darkness.len() - 1).max(1) as f32;
+        let (weighted, total) =
+            darkness
+                .iter()
+                .enumerate()
+                .fold((0.0, 0.0), |(weighted, total), (i, d)| {
+                    let position = 2.0 * i as f32 / last - 1.0;
+                    (weighted + position * d, total + d)
+                });
+
+        LinePosition::Offset((weighted / total).clamp(-1.0, 1.0))
+    }
+}
+
+/// A PID controller
+#[derive(Copy, Clone, Debug)]
+pub struct Pid {
+    kp: f32,
+    ki: f32,
+    kd: f32,
+    integral: f32,
+    prev_error: Option<f32>,
+    prev_time: Option<Instant>,
+}
+
+impl Pid {
+    /// Create a PID controller with the proportional, integral and derivative gains
+    pub fn new(kp: f32, ki: f32, kd: f32) -> Self {
+        Self {
+            kp,
+            ki,
+            kd,
+            integral: 0.0,
+            prev_error: None,
+            prev_time: None,
+        }
+    }
+
+    /// Clear the accumulated integral and derivative state
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.prev_error = None;
+        self.prev_time = None;
+    }
+
+    /// Compute the controller output for `error`
+    pub fn update(&mut self, error: f32) -> f32 {
+        let now = Instant::now();
+        let dt = self
+            .prev_time
+            .map(|prev| now.duration_since(prev).as_secs_f32())
+            .filter(|&dt| dt > 0.0);
+
+        let mut derivative = 0.0;
+        if let Some(dt) = dt {
+            self.integral += error * dt;
+            if let Some(prev_error) = self.prev_error {
+                derivative = (error - prev_error) / dt;
+            }
+        }
+        self.prev_error = Some(error);
+        self.prev_time = Some(now);
+
+        self.kp * error + self.ki * self.integral + self.kd * derivative
+    }
+}
+
+/// Drives [`Motors`] to follow a dark line seen by a [`Grayscale`] sensor
+///
+/// The sensor and motors are borrowed on every [`LineFollower::step`], so they stay
+/// available for cliff detection or battery monitoring in the same control loop.
+pub struct LineFollower {
+    estimator: LineEstimator,
+    pid: Pid,
+    speed: i8,
+    last_offset: f32,
+}
+
+impl LineFollower {
+    /// Create a line follower driving at base `speed`
+    ///
+    /// Range --> (0 - 100)
+    pub fn new(speed: i8) -> Self {
+        Self {
+            estimator: LineEstimator::new(),
+            pid: Pid::new(KP, KI, KD),
+            speed: speed.clamp(0, 100),
+            last_offset: 0.0,
+        }
+    }
+
+    /// Set the [`LineEstimator`] used to locate the line
+    pub fn set_estimator(&mut self, estimator: LineEstimator) {
+        self.estimator = estimator;
+    }
+
+    /// Set the steering [`Pid`] gains `(default: 30.0, 0.0, 2.0)`
+    pub fn set_pid(&mut self, pid: Pid) {
+        self.pid = pid;
+    }
+
+    /// Set the base speed
+    ///
+    /// Range --> (0 - 100)
+    pub fn set_speed(&mut self, speed: i8) {
+        self.speed = speed.clamp(0, 100);
+    }
+
+    /// Read `grayscale` once and steer `motors` towards the line
+    ///
+    /// When the line is lost the robot turns towards the side it was last seen on,
+    /// and at an intersection it keeps going straight.
+    pub fn step<const N: usize>(
+        &mut self,
+        grayscale: &mut Grayscale<N>,
+        motors: &mut Motors,
+    ) -> Result<LinePosition> {
+        let normalized = grayscale
+            .read_normalized()
+            .context("Reading grayscale sensor failed")?;
+        let position = self.estimator.estimate(&normalized);
+
+        match position {
+            LinePosition::Offset(offset) => {
+                self.last_offset = offset;
+                let correction = self.pid.update(offset);
+                let speed = self.speed as f32;
+                let left = (speed + correction).clamp(-100.0, 100.0) as i8;
+                let right = (speed - correction).clamp(-100.0, 100.0) as i8;
+                motors.speed(left, right);
+            }
+            LinePosition::Lost => {
+                self.pid.reset();
+                if self.last_offset < 0.0 {
+                    motors.turn_left(self.speed);
+                } else {
+                    motors.turn_right(self.speed);
+                }
+            }
+            LinePosition::Intersection => motors.forward(self.speed),
+        }
+
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_offset(position: LinePosition, expected: f32) {
+        let LinePosition::Offset(offset) = position else {
+            panic!("expected an offset, found {:?}", position);
+        };
+        assert!(
+            (offset - expected).abs() < 1e-4,
+            "{} != {}",
+            offset,
+            expected
+        );
+    }
+
+    #[test]
+    fn reports_lost_line() {
+        let estimator = LineEstimator::new();
+
+        assert_eq!(estimator.estimate(&[1.0, 0.9, 1.0]), LinePosition::Lost);
+    }
+
+    #[test]
+    fn reports_intersection() {
+        let estimator = LineEstimator::new();
+
+        assert_eq!(
+            estimator.estimate(&[0.0, 0.1, 0.2]),
+            LinePosition::Intersection
+        );
+    }
+
+    #[test]
+    fn centres_line_under_middle_channel() {
+        let estimator = LineEstimator::new();
+
+        assert_offset(estimator.estimate(&[1.0, 0.0, 1.0]), 0.0);
+    }
+
+    #[test]
+    fn locates_line_under_outer_channels() {
+        let estimator = LineEstimator::new();
+
+        assert_offset(estimator.estimate(&[0.0, 1.0, 1.0]), -1.0);
+        assert_offset(estimator.estimate(&[1.0, 1.0, 0.0]), 1.0);
+        assert_offset(estimator.estimate(&[0.1, 0.9, 1.0]), -0.9);
+    }
+
+    #[test]
+    fn locates_line_with_five_channels() {
+        let estimator = LineEstimator::new();
+
+        assert_offset(estimator.estimate(&[1.0, 1.0, 1.0, 0.0, 1.0]), 0.5);
+        assert_offset(estimator.estimate(&[1.0, 1.0, 0.0, 0.0, 1.0]), 0.25);
+        assert_eq!(
+            estimator.estimate(&[1.0, 1.0, 1.0, 1.0, 1.0]),
+            LinePosition::Lost
+        );
+    }
+}
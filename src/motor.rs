@@ -81,7 +81,7 @@ impl Motors {
     /// Set motor speed
     ///
     /// Range --> (0 - 100)
-    fn speed(&mut self, left_speed: i8, right_speed: i8) {
+    pub(crate) fn speed(&mut self, left_speed: i8, right_speed: i8) {
         let _ = self.left_motor.speed(left_speed);
         let _ = self.right_motor.speed(-right_speed); // Negating as per robot-hat python module
     }
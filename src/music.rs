@@ -0,0 +1,465 @@
+//! Music Module
+//!
+//! Sounds are mixed in software and streamed to the robot-hat speaker through `aplay`.
+//! WAV files are decoded natively, other formats like OGG or MP3 are decoded with `ffmpeg`.
+
+use std::f32::consts::TAU;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{spawn, JoinHandle};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+
+use crate::speaker::{Speaker, SpeakerGuard};
+
+// Music Constants
+const SAMPLE_RATE: u32 = 44_100;
+const CHANNELS: u16 = 2;
+// Frames mixed per write to `aplay`, ~23ms
+const BLOCK_FRAMES: usize = 1024;
+
+/// A sound decoded to 16-bit stereo PCM at 44.1kHz
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Sound {
+    samples: Arc<Vec<i16>>,
+}
+
+impl Sound {
+    /// Load a sound file, decoding WAV natively and anything else with `ffmpeg`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let is_wav = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("wav"));
+        if is_wav {
+            let bytes = fs::read(path)
+                .with_context(|| format!("Reading sound {} failed", path.display()))?;
+            return Self::from_wav(&bytes)
+                .with_context(|| format!("Decoding sound {} failed", path.display()));
+        }
+
+        let output = Command::new("ffmpeg")
+            .args(["-loglevel", "error", "-i"])
+            .arg(path)
+            .args(["-f", "s16le", "-ac", "2", "-ar", "44100", "-"])
+            .output();
+        let output = match output {
+            Ok(output) => output,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                bail!("ffmpeg is not installed, install it to play non-WAV files")
+            }
+            Err(err) => return Err(err).context("Running ffmpeg failed"),
+        };
+        if !output.status.success() {
+            bail!(
+                "Decoding sound {} failed: {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        }
+        let samples = output
+            .stdout
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+
+        Ok(Self::from_stereo(samples))
+    }
+
+    /// Decode a PCM WAV file held in memory
+    pub fn from_wav(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            bail!("Not a RIFF/WAVE file")
+        }
+
+        let mut format = None;
+        let mut data = None;
+        let mut rest = &bytes[12..];
+        while rest.len() >= 8 {
+            let (header, chunk) = rest.split_at(8);
+            let id = &header[0..4];
+            let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+            let body = chunk.get(..size).context("WAV chunk is truncated")?;
+            match id {
+                b"fmt " if size >= 16 => {
+                    let tag = u16::from_le_bytes([body[0], body[1]]);
+                    let channels = u16::from_le_bytes([body[2], body[3]]);
+                    let rate = u32::from_le_bytes([body[4], body[5], body[6], body[7]]);
+                    let bits = u16::from_le_bytes([body[14], body[15]]);
+                    format = Some((tag, channels, rate, bits));
+                }
+                b"data" => data = Some(body),
+                _ => {}
+            }
+            // Chunks are padded to an even size
+            rest = chunk[size..].get(size % 2..).unwrap_or_default();
+        }
+
+        let (tag, channels, rate, bits) = format.context("WAV has no fmt chunk")?;
+        let data = data.context("WAV has no data chunk")?;
+        if tag != 1 || channels == 0 {
+            bail!("Only PCM WAV files are supported")
+        }
+        let samples: Vec<i16> = match bits {
+            8 => data.iter().map(|&b| (b as i16 - 128) << 8).collect(),
+            16 => data
+                .chunks_exact(2)
+                .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                .collect(),
+            _ => bail!(
+                "Only 8 and 16 bit WAV files are supported, found {} bit",
+                bits
+            ),
+        };
+
+        Ok(Self::from_pcm(&samples, rate, channels))
+    }
+
+    /// Create a sound from interleaved 16-bit PCM `samples` with `channels` at `rate` Hz
+    pub fn from_pcm(samples: &[i16], rate: u32, channels: u16) -> Self {
+        let channels = channels.max(1) as usize;
+        let frames = samples.len() / channels;
+        let frame = |i: usize| {
+            let left = samples[i * channels];
+            let right = samples[i * channels + (channels > 1) as usize];
+            (left as f32, right as f32)
+        };
+
+        // Linearly resample to the output rate
+        let out_frames = (frames as u64 * SAMPLE_RATE as u64 / rate.max(1) as u64) as usize;
+        let mut stereo = Vec::with_capacity(out_frames * 2);
+        for i in 0..out_frames {
+            let pos = i as f64 * rate as f64 / SAMPLE_RATE as f64;
+            let index = (pos as usize).min(frames.saturating_sub(1));
+            let next = (index + 1).min(frames.saturating_sub(1));
+            let t = (pos - index as f64) as f32;
+            let (l0, r0) = frame(index);
+            let (l1, r1) = frame(next);
+            stereo.push((l0 + (l1 - l0) * t) as i16);
+            stereo.push((r0 + (r1 - r0) * t) as i16);
+        }
+
+        Self::from_stereo(stereo)
+    }
+
+    /// Generate a sine tone at `freq` Hz lasting `duration`
+    ///
+    /// Range --> volume (0.0 - 1.0)
+    pub fn tone(freq: f32, duration: Duration, volume: f32) -> Self {
+        let frames = (duration.as_secs_f32() * SAMPLE_RATE as f32) as usize;
+        let amplitude = volume.clamp(0.0, 1.0) * i16::MAX as f32;
+        // Short fades avoid clicks at the start and end
+        let fade = (SAMPLE_RATE as usize / 200).min(frames / 2).max(1) as f32;
+        let mut samples = Vec::with_capacity(frames * 2);
+        for i in 0..frames {
+            let envelope = (i as f32 / fade).min((frames - i) as f32 / fade).min(1.0);
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let sample = ((TAU * freq * t).sin() * amplitude * envelope) as i16;
+            samples.extend([sample, sample]);
+        }
+
+        Self::from_stereo(samples)
+    }
+
+    /// Length of the sound
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.samples.len() as f64 / 2.0 / SAMPLE_RATE as f64)
+    }
+
+    /// Write the sound to a WAV file at `path`
+    pub fn save_wav(&self, path: impl AsRef<Path>) -> Result<()> {
+        write_wav(path.as_ref(), &self.samples)
+    }
+
+    fn from_stereo(samples: Vec<i16>) -> Self {
+        Self {
+            samples: Arc::new(samples),
+        }
+    }
+}
+
+fn write_wav(path: &Path, samples: &[i16]) -> Result<()> {
+    let data_len = (samples.len() * 2) as u32;
+    let block_align = CHANNELS * 2;
+    let mut bytes = Vec::with_capacity(44 + data_len as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&CHANNELS.to_le_bytes());
+    bytes.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    bytes.extend_from_slice(&(SAMPLE_RATE * block_align as u32).to_le_bytes());
+    bytes.extend_from_slice(&block_align.to_le_bytes());
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        bytes.extend_from_slice(&sample.to_le_bytes());
+    }
+
+    fs::write(path, bytes).with_context(|| format!("Writing WAV {} failed", path.display()))
+}
+
+struct Voice {
+    sound: Sound,
+    pos: usize,
+    volume: f32,
+    looping: bool,
+}
+
+impl Voice {
+    // Mix into `out`, returning `false` once the voice has finished
+    fn mix(&mut self, out: &mut [f32], volume: f32) -> bool {
+        let samples = &self.sound.samples;
+        for sample in out.iter_mut() {
+            if self.pos >= samples.len() {
+                if !self.looping || samples.is_empty() {
+                    return false;
+                }
+                self.pos = 0;
+            }
+            *sample += samples[self.pos] as f32 * self.volume * volume;
+            self.pos += 1;
+        }
+
+        true
+    }
+}
+
+struct Mixer {
+    music: Option<Voice>,
+    effects: Vec<Voice>,
+    volume: f32,
+    music_volume: f32,
+    paused: bool,
+}
+
+impl Mixer {
+    fn is_active(&self) -> bool {
+        !self.paused && (self.music.is_some() || !self.effects.is_empty())
+    }
+
+    fn fill(&mut self, out: &mut [i16]) {
+        if self.paused {
+            out.fill(0);
+            return;
+        }
+
+        let mut mix = vec![0.0; out.len()];
+        if let Some(music) = &mut self.music {
+            if !music.mix(&mut mix, self.music_volume) {
+                self.music = None;
+            }
+        }
+        self.effects.retain_mut(|effect| effect.mix(&mut mix, 1.0));
+        for (out, sample) in out.iter_mut().zip(mix) {
+            *out = (sample * self.volume).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        }
+    }
+}
+
+/// A robot-hat music player mixing background music and sound effects
+pub struct Music {
+    mixer: Arc<Mutex<Mixer>>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Music {
+    /// Create a music player streaming to the robot-hat speaker through `aplay`
+    pub fn new() -> Result<Self> {
+        let speaker = Speaker::shared().context("Enabling speaker failed")?;
+        let child = Command::new("aplay")
+            .args(["-q", "-t", "raw", "-f", "S16_LE", "-r", "44100", "-c", "2"])
+            .stdin(Stdio::piped())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                bail!("aplay is not installed, install alsa-utils to play sound")
+            }
+            Err(err) => return Err(err).context("Running aplay failed"),
+        };
+
+        let mut music = Self::offline();
+        let mixer = Arc::clone(&music.mixer);
+        let stop = Arc::clone(&music.stop);
+        music.handle = Some(spawn(move || stream(&mut child, &mixer, &stop, speaker)));
+
+        Ok(music)
+    }
+
+    /// Create a music player without audio output, for use with [`Music::render_wav`]
+    pub fn offline() -> Self {
+        let mixer = Mixer {
+            music: None,
+            effects: Vec::new(),
+            volume: 1.0,
+            music_volume: 1.0,
+            paused: false,
+        };
+
+        Self {
+            mixer: Arc::new(Mutex::new(mixer)),
+            stop: Arc::new(AtomicBool::new(false)),
+            handle: None,
+        }
+    }
+
+    /// Start `sound` as background music, replacing the current music
+    pub fn play_music(&self, sound: &Sound, looping: bool) {
+        self.mixer().music = Some(Voice {
+            sound: sound.clone(),
+            pos: 0,
+            volume: 1.0,
+            looping,
+        });
+    }
+
+    /// Stop the background music, leaving sound effects playing
+    pub fn stop_music(&self) {
+        self.mixer().music = None;
+    }
+
+    /// Play `sound` once on top of the music and other sound effects
+    ///
+    /// Range --> volume (0.0 - 1.0)
+    pub fn play_effect(&self, sound: &Sound, volume: f32) {
+        self.mixer().effects.push(Voice {
+            sound: sound.clone(),
+            pos: 0,
+            volume: volume.clamp(0.0, 1.0),
+            looping: false,
+        });
+    }
+
+    /// Set the master volume `(default: 1.0)`
+    ///
+    /// Range --> (0.0 - 1.0)
+    pub fn set_volume(&self, volume: f32) {
+        self.mixer().volume = volume.clamp(0.0, 1.0);
+    }
+
+    /// Set the background music volume `(default: 1.0)`
+    ///
+    /// Range --> (0.0 - 1.0)
+    pub fn set_music_volume(&self, volume: f32) {
+        self.mixer().music_volume = volume.clamp(0.0, 1.0);
+    }
+
+    /// Pause all playback
+    pub fn pause(&self) {
+        self.mixer().paused = true;
+    }
+
+    /// Resume playback after [`Music::pause`]
+    pub fn resume(&self) {
+        self.mixer().paused = false;
+    }
+
+    /// Stop the music and all sound effects
+    pub fn stop(&self) {
+        let mut mixer = self.mixer();
+        mixer.music = None;
+        mixer.effects.clear();
+    }
+
+    /// Check whether any music or sound effect is playing
+    pub fn is_playing(&self) -> bool {
+        self.mixer().is_active()
+    }
+
+    /// Mix the next `duration` of playback into a WAV file at `path` instead of the speaker
+    ///
+    /// Useful with [`Music::offline`] to test without audio hardware.
+    pub fn render_wav(&self, duration: Duration, path: impl AsRef<Path>) -> Result<()> {
+        let frames = (duration.as_secs_f64() * SAMPLE_RATE as f64) as usize;
+        let mut samples = vec![0; frames * 2];
+        self.mixer().fill(&mut samples);
+
+        write_wav(path.as_ref(), &samples)
+    }
+
+    fn mixer(&self) -> std::sync::MutexGuard<'_, Mixer> {
+        self.mixer.lock().unwrap()
+    }
+}
+
+impl Drop for Music {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn stream(child: &mut Child, mixer: &Mutex<Mixer>, stop: &AtomicBool, speaker: &Speaker) {
+    let mut block = vec![0i16; BLOCK_FRAMES * 2];
+    let mut bytes = Vec::with_capacity(block.len() * 2);
+    // Keeps the amplifier on only while something is playing
+    let mut guard: Option<SpeakerGuard> = None;
+    if let Some(stdin) = child.stdin.as_mut() {
+        while !stop.load(Ordering::Relaxed) {
+            {
+                let mut mixer = mixer.lock().unwrap();
+                if mixer.is_active() {
+                    guard.get_or_insert_with(|| speaker.acquire());
+                } else {
+                    guard = None;
+                }
+                mixer.fill(&mut block);
+            }
+            bytes.clear();
+            bytes.extend(block.iter().flat_map(|sample| sample.to_le_bytes()));
+            // Writes block once the pipe is full, pacing the mixer to the playback rate
+            if stdin.write_all(&bytes).is_err() {
+                break;
+            }
+        }
+    }
+
+    // Closing stdin lets aplay drain and exit
+    drop(child.stdin.take());
+    let _ = child.wait();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    #[test]
+    fn render_wav_round_trip() {
+        let path = env::temp_dir().join(format!("robot-hat-music-{}.wav", process::id()));
+        let music = Music::offline();
+        music.play_effect(&Sound::tone(440.0, Duration::from_millis(200), 0.5), 1.0);
+        music.render_wav(Duration::from_millis(500), &path).unwrap();
+        let sound = Sound::load(&path);
+        let _ = fs::remove_file(&path);
+        let sound = sound.unwrap();
+
+        assert_eq!(sound.samples.len(), SAMPLE_RATE as usize / 2 * 2);
+        assert!(sound
+            .samples
+            .iter()
+            .any(|&sample| sample.abs() > i16::MAX / 4));
+        assert!(!music.is_playing());
+    }
+
+    #[test]
+    fn from_wav_rejects_oversized_chunk() {
+        let mut bytes = b"RIFF\0\0\0\0WAVEdata".to_vec();
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+
+        assert!(Sound::from_wav(&bytes).is_err());
+    }
+}
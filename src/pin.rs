@@ -1,18 +1,61 @@
 //! Pin abstraction layer for robot-hat
 
+use std::sync::Mutex;
+
 use anyhow::{Context, Result};
 use rppal::gpio::{self, Gpio};
 
 const BOARD_TYPE: u8 = 12;
 
-fn check_board_type() -> Result<bool> {
-    let type_pin = Gpio::new()?.get(BOARD_TYPE)?.into_input();
+// Revision detected or forced for this process
+static REVISION: Mutex<Option<BoardRevision>> = Mutex::new(None);
+
+/// A robot-hat board revision, telling the two pin mappings apart
+///
+/// Detected from the board type pin (BCM 12), which changes the BCM numbers of
+/// [`PinType::D1`], [`PinType::SW`], [`PinType::User`] and [`PinType::McuRst`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BoardRevision {
+    /// Board type pin pulled low --> D1: 18, SW/User: 19, McuRst: 21
+    Rev1,
+    /// Board type pin pulled high --> D1: 4, SW/User: 25, McuRst: 5
+    Rev2,
+}
+
+impl BoardRevision {
+    /// Read the board type pin, without caching
+    pub fn detect() -> Result<Self> {
+        let type_pin = Gpio::new()?.get(BOARD_TYPE)?.into_input();
+
+        if type_pin.is_low() {
+            Ok(BoardRevision::Rev1)
+        } else {
+            Ok(BoardRevision::Rev2)
+        }
+    }
+
+    /// Get the revision of this board, detected once per process unless forced
+    pub fn current() -> Result<Self> {
+        let mut revision = REVISION.lock().unwrap();
+        if let Some(revision) = *revision {
+            return Ok(revision);
+        }
+        let detected = Self::detect().context("Checking Board type failed")?;
+        *revision = Some(detected);
+
+        Ok(detected)
+    }
 
-    Ok(type_pin.is_low())
+    /// Use `revision` for every pin created afterwards instead of detecting it
+    ///
+    /// Useful for testing or boards with unusual wiring.
+    pub fn force(revision: BoardRevision) {
+        *REVISION.lock().unwrap() = Some(revision);
+    }
 }
 
 /// An explicit allowable types for [`RHPin`]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum PinType {
     /// The Digital pin 0
     D0,
@@ -108,10 +151,18 @@ pub enum PinType {
     BleRst,
     /// The MCU reset pin
     McuRst,
+    /// The speaker amplifier enable pin
+    Speaker,
 }
 
 impl PinType {
-    fn bcm_num(&self, board_type: bool) -> u8 {
+    /// Get the BCM number of the pin on a board with `revision`
+    ///
+    /// # Panics
+    ///
+    /// Panics for ADC and PWM pins, which are not GPIO pins.
+    pub fn bcm_num(&self, revision: BoardRevision) -> u8 {
+        let board_type = revision == BoardRevision::Rev1;
         match self {
             PinType::D0 => 17,
             PinType::D1 => {
@@ -155,6 +206,7 @@ impl PinType {
             PinType::Rst => 16,
             PinType::BleInt => 13,
             PinType::BleRst => 20,
+            PinType::Speaker => 20,
             PinType::McuRst => {
                 if board_type {
                     21
@@ -279,8 +331,7 @@ pub struct RHPin {
 impl RHPin {
     /// Create a new robot-hat pin using any [`PinType`]
     pub fn new(pin_type: PinType) -> Result<Self> {
-        let board_type = check_board_type().context("Checking Board type failed")?;
-        let bcm_num = pin_type.bcm_num(board_type);
+        let bcm_num = pin_type.bcm_num(BoardRevision::current()?);
         let gpio_pin = Gpio::new()?.get(bcm_num)?;
 
         Ok(Self { gpio_pin, bcm_num })
@@ -1,19 +1,25 @@
 //! PWM Module
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use rppal::i2c::I2c;
 
-use crate::{pin::PinType, utils::init_i2c};
+use crate::{
+    pin::PinType,
+    utils::{init_i2c, reset_generation, track},
+};
 
 const REG_PW: u8 = 0x20; // REG_CHN
 const REG_PSC: u8 = 0x40; // REG_PSC
 const REG_PER: u8 = 0x44; // REG_ARR
+const CLOCK: u32 = 72_000_000;
 
 /// A robot-hat PWM
 pub struct PWM {
     channel: u8,
+    prescaler: u16,
     period: Vec<u16>,
     bus: I2c,
+    generation: u32,
 }
 
 impl PWM {
@@ -24,8 +30,10 @@ impl PWM {
         let bus = init_i2c().context("I2C INIT FAILED")?;
         let mut pwm = Self {
             channel,
+            prescaler: 1,
             period,
             bus,
+            generation: reset_generation(),
         };
 
         pwm.freq(50).context("PWM FREQ INIT FAILED")?;
@@ -35,31 +43,28 @@ impl PWM {
 
     /// Set the frequency of the pwm channel
     ///
-    /// Range --> (0 - 65535)Hz
+    /// The prescaler and period belong to a timer shared by four channels
+    /// (P0-P3, P4-P7, P8-P11, P12-P13), so this changes the frequency of every
+    /// channel in the group, including ones owned by other [`PWM`]s.
+    ///
+    /// Range --> (1 - 65535)Hz
     pub fn freq(&mut self, freq: u16) -> Result<()> {
-        /*  Buggy code: For now, we hardcode the values
-                let mut result_psc = Vec::with_capacity(12); // Create a vector for prescaler
-                let mut result_per = Vec::with_capacity(12); // Create a vector for period
-                let mut result_acy = Vec::with_capacity(12); // Create a vector for accuracy
-
-                let st = ((CLOCK as f32 / freq as f32).sqrt() as u16) - 5;
-
-                for psc in st..st + 10 {
-                    let per = (CLOCK / (freq * psc) as u32) as u16;
-                    result_psc.push(psc);
-                    result_per.push(per);
-                    result_acy.push(f32::abs(freq as f32 - CLOCK as f32 / (psc * per) as f32));
-                }
-
-                let i = result_acy
-                    .iter()
-                    .position(|&x| x == result_acy.iter().cloned().fold(f32::INFINITY, f32::min))
-                    .unwrap();
-                let psc = result_psc[i];
-                let per = result_per[i];
-        */
-        let psc: u16 = freq * 24; // 1200
-        let per: u16 = freq * 24; // 1200
+        if freq == 0 {
+            bail!("PWM frequency should be greater than 0Hz")
+        }
+
+        // Search prescalers around sqrt(CLOCK / freq) for the closest achievable frequency
+        let freq = freq as u32;
+        let st = ((CLOCK / freq) as f32).sqrt() as u32;
+        let (psc, per) = (st.saturating_sub(5).max(1)..st + 5)
+            .map(|psc| (psc, (CLOCK / (freq * psc)).clamp(1, u16::MAX as u32)))
+            .min_by(|&(psc_a, per_a), &(psc_b, per_b)| {
+                let error =
+                    |psc: u32, per: u32| (freq as f64 - CLOCK as f64 / (psc * per) as f64).abs();
+                error(psc_a, per_a).total_cmp(&error(psc_b, per_b))
+            })
+            .context("PWM FREQ SEARCH FAILED")?;
+        let (psc, per) = (psc.min(u16::MAX as u32) as u16, per as u16);
 
         self.prescaler(psc).context("PWM PRESCALER INIT FAILED")?;
         self.period(per).context("PWM PERIOD INIT FAILED")?;
@@ -67,29 +72,34 @@ impl PWM {
         Ok(())
     }
 
-    /// Set the prescaler for the pwm channel
+    /// Set the prescaler for the pwm channel, shared by its group of four channels
     ///
     /// Range --> (0 - 65535)
     pub fn prescaler(&mut self, prescaler: u16) -> Result<()> {
         let timer = self.channel / 4_u8;
         let reg = REG_PSC + timer;
-        self.bus
-            .smbus_write_word(reg, (prescaler - 1).swap_bytes())
-            .context("PWM PRESCALER SEND FAILED")?;
+        self.prescaler = prescaler;
+        track(
+            self.bus
+                .smbus_write_word(reg, (prescaler - 1).swap_bytes())
+                .context("PWM PRESCALER SEND FAILED"),
+        )?;
 
         Ok(())
     }
 
-    /// Set the period for the pwm channel
+    /// Set the period for the pwm channel, shared by its group of four channels
     ///
     /// Range --> (0 - 65535)
     pub fn period(&mut self, per: u16) -> Result<()> {
         let timer = self.channel / 4_u8;
         let reg = REG_PER + timer;
         self.period[timer as usize] = per - 1;
-        self.bus
-            .smbus_write_word(reg, self.period[timer as usize].swap_bytes())
-            .context("PWM PERIOD SEND FAILED")?;
+        track(
+            self.bus
+                .smbus_write_word(reg, self.period[timer as usize].swap_bytes())
+                .context("PWM PERIOD SEND FAILED"),
+        )?;
 
         Ok(())
     }
@@ -98,24 +108,49 @@ impl PWM {
     ///
     /// Range --> (0 - 65535)
     pub fn pulse_width(&mut self, pw: u16) -> Result<()> {
+        self.restore()?;
         let reg = REG_PW + self.channel;
-        self.bus
-            .smbus_write_word(reg, pw.swap_bytes())
-            .context("PWM PULSE WIDTH SEND FAILED")?;
+        track(
+            self.bus
+                .smbus_write_word(reg, pw.swap_bytes())
+                .context("PWM PULSE WIDTH SEND FAILED"),
+        )?;
 
         Ok(())
     }
 
+    /// Get the pulse width of a 100% duty cycle for the pwm channel, set by [`PWM::period`]
+    pub fn max_pulse_width(&self) -> u16 {
+        self.period[(self.channel / 4_u8) as usize]
+    }
+
     /// Set the pulse width percentage for the pwm channel
     ///
     /// Range --> (0 - 100)%
     pub fn pulse_width_percent(&mut self, pulse_width_percent: u8) -> Result<()> {
-        // Buggy code ? !!
         let pulse_width_percent = pulse_width_percent.clamp(0, 100);
         let timer = self.channel / 4_u8;
-        let pulse_width = (self.period[timer as usize] * pulse_width_percent as u16) / 100;
+        let pulse_width =
+            (self.period[timer as usize] as u32 * pulse_width_percent as u32 / 100) as u16;
         self.pulse_width(pulse_width)?;
 
         Ok(())
     }
+
+    // Re-send the timer settings if the MCU was reset since they were last sent
+    fn restore(&mut self) -> Result<()> {
+        let generation = reset_generation();
+        if generation == self.generation {
+            return Ok(());
+        }
+
+        let timer = (self.channel / 4_u8) as usize;
+        self.prescaler(self.prescaler)
+            .context("PWM PRESCALER RESTORE FAILED")?;
+        self.period(self.period[timer] + 1)
+            .context("PWM PERIOD RESTORE FAILED")?;
+        self.generation = generation;
+
+        Ok(())
+    }
 }
@@ -0,0 +1,273 @@
+//! RGB LED Module
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{pin::PinType, pwm::PWM};
+
+// RGB LED Constants
+const GAMMA: f32 = 2.2;
+const EFFECT_STEP: Duration = Duration::from_millis(20);
+// High enough not to flicker, low enough to keep ~270 duty steps
+const PWM_FREQ: u16 = 1000;
+
+/// How the RGB LED is wired
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Wiring {
+    /// The common pin is connected to ground, channels are lit when driven high `(default)`
+    #[default]
+    CommonCathode,
+    /// The common pin is connected to the supply, channels are lit when driven low
+    CommonAnode,
+}
+
+/// An RGB colour
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Color {
+    /// Red channel
+    pub r: u8,
+    /// Green channel
+    pub g: u8,
+    /// Blue channel
+    pub b: u8,
+}
+
+impl Color {
+    /// Create a colour from red, green and blue channels
+    pub fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// Create a colour from a hex string like `#ff8000` or `ff8000`
+    pub fn hex(hex: &str) -> Result<Self> {
+        let digits = hex.trim().trim_start_matches('#');
+        if digits.len() != 6 {
+            bail!("Hex colour should have 6 digits, but passed {:?}", hex)
+        }
+        let value = u32::from_str_radix(digits, 16)
+            .with_context(|| format!("Invalid hex colour {:?}", hex))?;
+
+        Ok(Self::rgb(
+            (value >> 16) as u8,
+            (value >> 8) as u8,
+            value as u8,
+        ))
+    }
+
+    /// Create a colour from hue, saturation and value
+    ///
+    /// Range --> hue (0.0 - 360.0), saturation and value (0.0 - 1.0)
+    pub fn hsv(hue: f32, saturation: f32, value: f32) -> Self {
+        let hue = hue.rem_euclid(360.0) / 60.0;
+        let saturation = saturation.clamp(0.0, 1.0);
+        let value = value.clamp(0.0, 1.0);
+
+        let chroma = value * saturation;
+        let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+        let (r, g, b) = match hue as u8 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let m = value - chroma;
+        let channel = |c: f32| ((c + m) * 255.0).round() as u8;
+
+        Self::rgb(channel(r), channel(g), channel(b))
+    }
+}
+
+struct Channels {
+    pwms: [PWM; 3],
+    wiring: Wiring,
+    brightness: f32,
+    gamma: bool,
+}
+
+impl Channels {
+    fn write(&mut self, color: Color) -> Result<()> {
+        for (pwm, value) in self.pwms.iter_mut().zip([color.r, color.g, color.b]) {
+            let mut level = value as f32 / 255.0 * self.brightness;
+            if self.gamma {
+                level = level.powf(GAMMA);
+            }
+            // Full timer resolution keeps dim gamma corrected levels smooth
+            let max = pwm.max_pulse_width();
+            let mut pulse_width = (level.clamp(0.0, 1.0) * max as f32).round() as u16;
+            if self.wiring == Wiring::CommonAnode {
+                pulse_width = max - pulse_width;
+            }
+            pwm.pulse_width(pulse_width)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A built-in [`RgbLed`] effect
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Effect {
+    /// Switch between `color` and off, each lasting `period / 2`
+    Blink {
+        /// The colour when on
+        color: Color,
+        /// Duration of one on/off cycle
+        period: Duration,
+    },
+    /// Fade `color` in and out
+    Breathe {
+        /// The colour at full brightness
+        color: Color,
+        /// Duration of one fade in/out cycle
+        period: Duration,
+    },
+    /// Cycle through all hues
+    Rainbow {
+        /// Duration of one cycle through all hues
+        period: Duration,
+    },
+}
+
+impl Effect {
+    fn color_at(&self, elapsed: Duration) -> Color {
+        let phase = |period: Duration| {
+            elapsed.as_secs_f32() % period.as_secs_f32().max(f32::EPSILON)
+                / period.as_secs_f32().max(f32::EPSILON)
+        };
+        match *self {
+            Effect::Blink { color, period } => {
+                if phase(period) < 0.5 {
+                    color
+                } else {
+                    Color::default()
+                }
+            }
+            Effect::Breathe { color, period } => {
+                let level = 1.0 - (phase(period) * 2.0 - 1.0).abs();
+                let scale = |c: u8| (c as f32 * level).round() as u8;
+                Color::rgb(scale(color.r), scale(color.g), scale(color.b))
+            }
+            Effect::Rainbow { period } => Color::hsv(phase(period) * 360.0, 1.0, 1.0),
+        }
+    }
+}
+
+/// A robot-hat RGB LED driven by 3 pwm pins
+pub struct RgbLed {
+    channels: Option<Channels>,
+    effect: Option<(Arc<AtomicBool>, JoinHandle<Channels>)>,
+}
+
+impl RgbLed {
+    /// Create an RGB LED using red, green and blue pwm pins with [`PinType`]  *(P0-P13)*
+    ///
+    /// The pins are driven at 1kHz to avoid flicker. This retunes the timer shared by each
+    /// pin's group of four channels (P0-P3, P4-P7, P8-P11, P12-P13), so pick groups without
+    /// servos or motors, e.g. not P12/P13 which drive [`Motors`](crate::motor::Motors).
+    pub fn new(red: PinType, green: PinType, blue: PinType, wiring: Wiring) -> Result<Self> {
+        let mut red = PWM::new(red).context("RED PWM INIT FAILED")?;
+        let mut green = PWM::new(green).context("GREEN PWM INIT FAILED")?;
+        let mut blue = PWM::new(blue).context("BLUE PWM INIT FAILED")?;
+        for pwm in [&mut red, &mut green, &mut blue] {
+            pwm.freq(PWM_FREQ).context("RGB LED PWM FREQ INIT FAILED")?;
+        }
+
+        let mut channels = Channels {
+            pwms: [red, green, blue],
+            wiring,
+            brightness: 1.0,
+            gamma: true,
+        };
+        channels.write(Color::default())?;
+
+        Ok(Self {
+            channels: Some(channels),
+            effect: None,
+        })
+    }
+
+    /// Set the brightness applied to every colour `(default: 1.0)`, stopping any running effect
+    ///
+    /// Range --> (0.0 - 1.0)
+    pub fn set_brightness(&mut self, brightness: f32) -> Result<()> {
+        self.channels()?.brightness = brightness.clamp(0.0, 1.0);
+
+        Ok(())
+    }
+
+    /// Enable or disable gamma correction `(default: true)`, stopping any running effect
+    pub fn set_gamma_correction(&mut self, enabled: bool) -> Result<()> {
+        self.channels()?.gamma = enabled;
+
+        Ok(())
+    }
+
+    /// Show a [`Color`], stopping any running effect
+    pub fn set_color(&mut self, color: Color) -> Result<()> {
+        self.channels()?.write(color)
+    }
+
+    /// Switch the LED off, stopping any running effect
+    pub fn off(&mut self) -> Result<()> {
+        self.set_color(Color::default())
+    }
+
+    /// Run an [`Effect`] on a background thread until another colour or effect is set
+    pub fn start_effect(&mut self, effect: Effect) -> Result<()> {
+        self.stop_effect()?;
+        let mut channels = self
+            .channels
+            .take()
+            .context("RGB LED channels are unavailable")?;
+        let running = Arc::new(AtomicBool::new(true));
+        let keep_running = Arc::clone(&running);
+
+        let handle = spawn(move || {
+            let start = Instant::now();
+            while keep_running.load(Ordering::Relaxed) {
+                if channels.write(effect.color_at(start.elapsed())).is_err() {
+                    break;
+                }
+                sleep(EFFECT_STEP);
+            }
+
+            channels
+        });
+        self.effect = Some((running, handle));
+
+        Ok(())
+    }
+
+    /// Stop the running effect, if any, leaving the LED at its last colour
+    pub fn stop_effect(&mut self) -> Result<()> {
+        if let Some((running, handle)) = self.effect.take() {
+            running.store(false, Ordering::Relaxed);
+            let channels = handle
+                .join()
+                .ok()
+                .context("RGB LED effect thread panicked")?;
+            self.channels = Some(channels);
+        }
+
+        Ok(())
+    }
+
+    fn channels(&mut self) -> Result<&mut Channels> {
+        self.stop_effect()?;
+        self.channels
+            .as_mut()
+            .context("RGB LED channels are unavailable")
+    }
+}
+
+impl Drop for RgbLed {
+    fn drop(&mut self) {
+        let _ = self.stop_effect();
+    }
+}
@@ -0,0 +1,249 @@
+//! RTTTL (Nokia ring tone) parser and player
+//!
+//! A ring tone looks like `Nokia:d=4,o=5,b=225:8e6,8d6,f#,g#,8c#6,8b,d,e,8b,8a,c#,e,2a`,
+//! with a name, the default duration, octave and tempo, and the notes.
+
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::buzzer::{note_freq, Buzzer, Melody, Tone};
+
+// RTTTL defaults when the section is omitted
+const DEFAULT_DURATION: u32 = 4;
+const DEFAULT_OCTAVE: u32 = 6;
+const DEFAULT_TEMPO: u32 = 63;
+const DURATIONS: [u32; 6] = [1, 2, 4, 8, 16, 32];
+const OCTAVES: std::ops::RangeInclusive<u32> = 4..=7;
+
+/// Error returned when parsing a malformed RTTTL string
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RtttlError {
+    /// The string is not made of `name:defaults:notes` sections
+    MissingSection,
+    /// A `d=`, `o=` or `b=` default is malformed or unknown
+    InvalidDefault(String),
+    /// A note has a duration other than 1, 2, 4, 8, 16 or 32
+    InvalidDuration(String),
+    /// A note is not one of `a-g` or `p`
+    InvalidNote(String),
+    /// A note has an octave outside 4-7
+    InvalidOctave(String),
+}
+
+impl fmt::Display for RtttlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RtttlError::MissingSection => {
+                write!(f, "RTTTL should have name:defaults:notes sections")
+            }
+            RtttlError::InvalidDefault(s) => write!(f, "Invalid RTTTL default {:?}", s),
+            RtttlError::InvalidDuration(s) => write!(f, "Invalid RTTTL duration in {:?}", s),
+            RtttlError::InvalidNote(s) => write!(f, "Invalid RTTTL note {:?}", s),
+            RtttlError::InvalidOctave(s) => write!(f, "Invalid RTTTL octave in {:?}", s),
+        }
+    }
+}
+
+impl Error for RtttlError {}
+
+/// A parsed RTTTL ring tone
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ringtone {
+    name: String,
+    melody: Melody,
+}
+
+impl Ringtone {
+    /// Parse an RTTTL string
+    pub fn parse(rtttl: &str) -> Result<Self, RtttlError> {
+        let mut sections = rtttl.trim().splitn(3, ':');
+        let (Some(name), Some(defaults), Some(notes)) =
+            (sections.next(), sections.next(), sections.next())
+        else {
+            return Err(RtttlError::MissingSection);
+        };
+
+        let mut duration = DEFAULT_DURATION;
+        let mut octave = DEFAULT_OCTAVE;
+        let mut tempo = DEFAULT_TEMPO;
+        for default in defaults.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let invalid = || RtttlError::InvalidDefault(default.to_string());
+            let (key, value) = default.split_once('=').ok_or_else(invalid)?;
+            let value: u32 = value.trim().parse().map_err(|_| invalid())?;
+            match key.trim() {
+                "d" if DURATIONS.contains(&value) => duration = value,
+                "o" if OCTAVES.contains(&value) => octave = value,
+                "b" if value > 0 => tempo = value,
+                _ => return Err(invalid()),
+            }
+        }
+
+        // Duration of a whole note, the tempo counts quarter notes
+        let whole = Duration::from_secs(60 * 4) / tempo;
+        let tones = notes
+            .split(',')
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+            .map(|note| parse_note(note, duration, octave, whole))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            name: name.trim().to_string(),
+            melody: Melody::from_tones(tempo as f32, tones),
+        })
+    }
+
+    /// Get the name of the ring tone
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the [`Melody`] of the ring tone
+    pub fn melody(&self) -> &Melody {
+        &self.melody
+    }
+
+    /// Play the ring tone through a passive [`Buzzer`], blocking until it finishes
+    pub fn play(&self, buzzer: &mut Buzzer) -> Result<()> {
+        buzzer.play(&self.melody)
+    }
+}
+
+fn parse_note(
+    note: &str,
+    default_duration: u32,
+    default_octave: u32,
+    whole: Duration,
+) -> Result<Tone, RtttlError> {
+    let lower = note.to_ascii_lowercase();
+    let digits = lower
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(lower.len());
+    let (duration, rest) = lower.split_at(digits);
+    let duration = if duration.is_empty() {
+        default_duration
+    } else {
+        duration
+            .parse()
+            .ok()
+            .filter(|d| DURATIONS.contains(d))
+            .ok_or_else(|| RtttlError::InvalidDuration(note.to_string()))?
+    };
+
+    let mut chars = rest.chars().peekable();
+    let letter = chars
+        .next()
+        .filter(|c| matches!(c, 'a'..='g' | 'p'))
+        .ok_or_else(|| RtttlError::InvalidNote(note.to_string()))?;
+    let sharp = chars.next_if_eq(&'#').is_some();
+    // The dot is allowed both before and after the octave
+    let mut dotted = chars.next_if_eq(&'.').is_some();
+    let octave: String = std::iter::from_fn(|| chars.next_if(char::is_ascii_digit)).collect();
+    dotted |= chars.next_if_eq(&'.').is_some();
+    if chars.next().is_some() {
+        return Err(RtttlError::InvalidNote(note.to_string()));
+    }
+
+    let octave = if octave.is_empty() {
+        default_octave
+    } else {
+        octave
+            .parse()
+            .ok()
+            .filter(|o| OCTAVES.contains(o))
+            .ok_or_else(|| RtttlError::InvalidOctave(note.to_string()))?
+    };
+
+    let mut duration = whole / duration;
+    if dotted {
+        duration += duration / 2;
+    }
+
+    let freq = if letter == 'p' {
+        None
+    } else {
+        let name = format!("{}{}{}", letter, if sharp { "#" } else { "" }, octave);
+        Some(note_freq(&name).map_err(|_| RtttlError::InvalidNote(note.to_string()))?)
+    };
+
+    Ok(Tone { freq, duration })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOKIA: &str = "Nokia:d=4,o=5,b=225:8e6,8d6,f#,g#,8c#6,8b,d,e,8b,8a,c#,e,2a";
+    const SIMPSONS: &str = "The Simpsons:d=4,o=5,b=160:c.6,e6,f#6,8a6,g.6,e6,c6,8a,8f#,8f#,8f#,2g,8p,8p,8f#,8f#,8f#,8g,a#.,8c6,8c6,8c6,c6";
+
+    fn assert_freq(tone: &Tone, expected: f32) {
+        let freq = tone.freq.expect("expected a note, found a rest");
+        assert!((freq - expected).abs() < 0.5, "{} != {}", freq, expected);
+    }
+
+    #[test]
+    fn parses_nokia_tune() {
+        let ringtone = Ringtone::parse(NOKIA).unwrap();
+        let tones = ringtone.melody().tones();
+
+        assert_eq!(ringtone.name(), "Nokia");
+        assert_eq!(tones.len(), 13);
+        assert_freq(&tones[0], 1318.51); // E6
+        assert_freq(&tones[2], 739.99); // F#5
+        assert_freq(&tones[12], 880.0); // A5
+        assert_eq!(tones[0].duration, Duration::from_secs(240) / 225 / 8);
+        assert_eq!(tones[2].duration, Duration::from_secs(240) / 225 / 4);
+        assert_eq!(tones[12].duration, Duration::from_secs(240) / 225 / 2);
+    }
+
+    #[test]
+    fn parses_dotted_notes_and_rests() {
+        let ringtone = Ringtone::parse(SIMPSONS).unwrap();
+        let tones = ringtone.melody().tones();
+        let quarter = Duration::from_secs(240) / 160 / 4;
+
+        assert_eq!(tones.len(), 23);
+        assert_freq(&tones[0], 1046.5); // C6
+        assert_eq!(tones[0].duration, quarter + quarter / 2);
+        assert_eq!(tones[12].freq, None);
+        assert_eq!(tones[12].duration, quarter / 2);
+        assert_freq(&tones[18], 932.33); // A#5
+        assert_eq!(tones[18].duration, quarter + quarter / 2);
+    }
+
+    #[test]
+    fn uses_defaults_when_omitted() {
+        let ringtone = Ringtone::parse("Beep::c").unwrap();
+        let tones = ringtone.melody().tones();
+
+        assert_freq(&tones[0], 1046.5); // C6
+        assert_eq!(tones[0].duration, Duration::from_secs(240) / 63 / 4);
+    }
+
+    #[test]
+    fn rejects_malformed_strings() {
+        assert_eq!(
+            Ringtone::parse("no sections"),
+            Err(RtttlError::MissingSection)
+        );
+        assert_eq!(
+            Ringtone::parse("Bad:d=3:c"),
+            Err(RtttlError::InvalidDefault("d=3".to_string()))
+        );
+        assert_eq!(
+            Ringtone::parse("Bad:d=4:3c"),
+            Err(RtttlError::InvalidDuration("3c".to_string()))
+        );
+        assert_eq!(
+            Ringtone::parse("Bad:d=4:8x"),
+            Err(RtttlError::InvalidNote("8x".to_string()))
+        );
+        assert_eq!(
+            Ringtone::parse("Bad:d=4:c9"),
+            Err(RtttlError::InvalidOctave("c9".to_string()))
+        );
+    }
+}
@@ -0,0 +1,86 @@
+//! Persistent robot-hat settings
+//!
+//! Settings are stored as `key = value` lines, like the config files of robot-hat (Python).
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+
+const DEFAULT_PATH: &str = ".config/robot-hat/robot-hat.conf";
+
+/// A robot-hat settings file
+#[derive(Clone, Debug)]
+pub struct Settings {
+    path: PathBuf,
+    entries: BTreeMap<String, String>,
+}
+
+impl Settings {
+    /// Open the settings file at `path`, starting empty if it does not exist yet
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Reading settings {} failed", path.display()))
+            }
+        };
+
+        let entries = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+            .collect();
+
+        Ok(Self { path, entries })
+    }
+
+    /// Open the default settings file `~/.config/robot-hat/robot-hat.conf`
+    pub fn open_default() -> Result<Self> {
+        let home = env::var_os("HOME").context("HOME is not set")?;
+
+        Self::open(Path::new(&home).join(DEFAULT_PATH))
+    }
+
+    /// Get the value stored for `key`, if present and parsable
+    pub fn get<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.entries.get(key).and_then(|value| value.parse().ok())
+    }
+
+    /// Store `value` for `key`
+    ///
+    /// Call [`Settings::save`] to write the changes to disk.
+    pub fn set<T: Display>(&mut self, key: &str, value: T) {
+        self.entries.insert(key.to_string(), value.to_string());
+    }
+
+    /// Remove the value stored for `key`
+    pub fn remove(&mut self, key: &str) {
+        self.entries.remove(key);
+    }
+
+    /// Write the settings to disk
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Creating settings dir {} failed", dir.display()))?;
+        }
+        let content: String = self
+            .entries
+            .iter()
+            .map(|(key, value)| format!("{} = {}\n", key, value))
+            .collect();
+        fs::write(&self.path, content)
+            .with_context(|| format!("Writing settings {} failed", self.path.display()))
+    }
+}
@@ -0,0 +1,136 @@
+//! Speaker amplifier Module
+
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use rppal::gpio::OutputPin;
+
+use crate::pin::{PinType, RHPin};
+
+// Speaker Constants
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+const IDLE_POLL: Duration = Duration::from_millis(100);
+
+struct State {
+    pin: OutputPin,
+    users: usize,
+    released: Instant,
+    idle_timeout: Option<Duration>,
+}
+
+/// The robot-hat speaker amplifier
+///
+/// The amplifier is switched on while a [`SpeakerGuard`] is alive and switched off
+/// again once it has been idle for the idle timeout.
+/// Get it with [`Speaker::shared`].
+pub struct Speaker {
+    state: Arc<Mutex<State>>,
+}
+
+/// Keeps the [`Speaker`] amplifier on until dropped
+pub struct SpeakerGuard {
+    state: Arc<Mutex<State>>,
+}
+
+impl Drop for SpeakerGuard {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        state.users = state.users.saturating_sub(1);
+        state.released = Instant::now();
+    }
+}
+
+impl Speaker {
+    // Claims PinType::Speaker, so only `shared` may create it
+    fn new() -> Result<Self> {
+        let pin = RHPin::new(PinType::Speaker).context("Creating speaker pin failed")?;
+        let state = Arc::new(Mutex::new(State {
+            pin: pin.gpio_pin.into_output_low(),
+            users: 0,
+            released: Instant::now(),
+            idle_timeout: Some(IDLE_TIMEOUT),
+        }));
+
+        let idle = Arc::downgrade(&state);
+        spawn(move || switch_off_when_idle(idle));
+
+        Ok(Self { state })
+    }
+
+    /// Get the speaker amplifier control using [`PinType::Speaker`]
+    ///
+    /// The speaker is shared by the whole process, including the sound modules.
+    pub fn shared() -> Result<&'static Speaker> {
+        static SPEAKER: OnceLock<Speaker> = OnceLock::new();
+        static INIT: Mutex<()> = Mutex::new(());
+
+        if let Some(speaker) = SPEAKER.get() {
+            return Ok(speaker);
+        }
+        // Only one thread may claim the pin
+        let _init = INIT.lock().unwrap();
+        if let Some(speaker) = SPEAKER.get() {
+            return Ok(speaker);
+        }
+        let speaker = Speaker::new()?;
+
+        Ok(SPEAKER.get_or_init(|| speaker))
+    }
+
+    /// Switch the amplifier on until it has been idle for the idle timeout
+    pub fn on(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.pin.set_high();
+        state.released = Instant::now();
+    }
+
+    /// Switch the amplifier off, unless a [`SpeakerGuard`] is alive
+    pub fn off(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.users == 0 {
+            state.pin.set_low();
+        }
+    }
+
+    /// Check whether the amplifier is on
+    pub fn is_on(&self) -> bool {
+        self.state.lock().unwrap().pin.is_set_high()
+    }
+
+    /// Set how long the amplifier stays on after the last use `(default: 5s)`
+    ///
+    /// `None` keeps it on until [`Speaker::off`] is called.
+    pub fn set_idle_timeout(&self, idle_timeout: Option<Duration>) {
+        self.state.lock().unwrap().idle_timeout = idle_timeout;
+    }
+
+    /// Switch the amplifier on and keep it on until the returned guard is dropped
+    pub fn acquire(&self) -> SpeakerGuard {
+        let mut state = self.state.lock().unwrap();
+        state.users += 1;
+        state.pin.set_high();
+
+        SpeakerGuard {
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+fn switch_off_when_idle(state: Weak<Mutex<State>>) {
+    // Runs until the speaker is dropped
+    while let Some(state) = state.upgrade() {
+        {
+            let mut state = state.lock().unwrap();
+            let idle = state
+                .idle_timeout
+                .is_some_and(|timeout| state.released.elapsed() >= timeout);
+            if state.users == 0 && idle && state.pin.is_set_high() {
+                state.pin.set_low();
+            }
+        }
+        drop(state);
+        sleep(IDLE_POLL);
+    }
+}
@@ -0,0 +1,215 @@
+//! Text-to-speech Module
+//!
+//! Speech is synthesised by locally installed engines and played with `aplay`.
+
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{bail, Context, Result};
+
+use crate::speaker::Speaker;
+
+// TTS Constants
+const SPEED: u32 = 175;
+const PITCH: u32 = 50;
+const VOLUME: u32 = 100;
+
+/// A text-to-speech engine
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Engine {
+    /// `espeak`
+    Espeak,
+    /// `espeak-ng` `(default)`
+    #[default]
+    EspeakNg,
+    /// `pico2wave` from SVOX Pico
+    Pico2Wave,
+}
+
+impl Engine {
+    /// Name of the engine executable
+    pub fn program(&self) -> &'static str {
+        match self {
+            Engine::Espeak => "espeak",
+            Engine::EspeakNg => "espeak-ng",
+            Engine::Pico2Wave => "pico2wave",
+        }
+    }
+
+    fn default_lang(&self) -> &'static str {
+        match self {
+            Engine::Espeak | Engine::EspeakNg => "en",
+            Engine::Pico2Wave => "en-US",
+        }
+    }
+}
+
+// Run `command`, reporting a missing `program` clearly
+fn run(mut command: Command, program: &str) -> Result<()> {
+    let output = match command.output() {
+        Ok(output) => output,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            bail!("{} is not installed, install it to use it", program)
+        }
+        Err(err) => return Err(err).with_context(|| format!("Running {} failed", program)),
+    };
+    if !output.status.success() {
+        bail!(
+            "{} failed with {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+    }
+
+    Ok(())
+}
+
+// Escape `text` for use inside pico2wave markup
+fn escape_markup(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '\'' => escaped.push_str("&apos;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Play a WAV file through the robot-hat speaker using `aplay`
+pub fn play_wav(path: impl AsRef<Path>) -> Result<()> {
+    let _speaker = Speaker::shared()
+        .context("Enabling speaker failed")?
+        .acquire();
+    let mut command = Command::new("aplay");
+    command.arg("-q").arg(path.as_ref());
+
+    run(command, "aplay")
+}
+
+/// A robot-hat text-to-speech synthesiser
+#[derive(Clone, Debug)]
+pub struct Tts {
+    engine: Engine,
+    lang: String,
+    speed: u32,
+    pitch: u32,
+    volume: u32,
+}
+
+impl Tts {
+    /// Create a text-to-speech synthesiser using `engine`
+    pub fn new(engine: Engine) -> Self {
+        Self {
+            engine,
+            lang: engine.default_lang().to_string(),
+            speed: SPEED,
+            pitch: PITCH,
+            volume: VOLUME,
+        }
+    }
+
+    /// Check whether the engine is installed
+    pub fn is_installed(&self) -> bool {
+        Command::new(self.engine.program())
+            .arg("--help")
+            .output()
+            .is_ok()
+    }
+
+    /// Set the language, e.g. `en` for espeak or `en-US` for pico2wave
+    pub fn set_lang(&mut self, lang: &str) {
+        self.lang = lang.to_string();
+    }
+
+    /// Set the speed in words per minute `(default: 175)`
+    ///
+    /// Range --> (80 - 450)
+    pub fn set_speed(&mut self, speed: u32) {
+        self.speed = speed.clamp(80, 450);
+    }
+
+    /// Set the pitch `(default: 50)`
+    ///
+    /// Range --> (0 - 99)
+    pub fn set_pitch(&mut self, pitch: u32) {
+        self.pitch = pitch.min(99);
+    }
+
+    /// Set the volume `(default: 100)`
+    ///
+    /// Range --> (0 - 200)
+    pub fn set_volume(&mut self, volume: u32) {
+        self.volume = volume.min(200);
+    }
+
+    /// Synthesise `text` to a WAV file at `path`
+    pub fn render(&self, text: &str, path: impl AsRef<Path>) -> Result<()> {
+        let program = self.engine.program();
+        let mut command = Command::new(program);
+        match self.engine {
+            Engine::Espeak | Engine::EspeakNg => {
+                command
+                    .arg("-v")
+                    .arg(&self.lang)
+                    .arg("-s")
+                    .arg(self.speed.to_string())
+                    .arg("-p")
+                    .arg(self.pitch.to_string())
+                    .arg("-a")
+                    .arg(self.volume.to_string())
+                    .arg("-w")
+                    .arg(path.as_ref())
+                    .arg("--")
+                    .arg(text);
+            }
+            Engine::Pico2Wave => {
+                // pico2wave has no flags for these, but understands markup in percent
+                let speed = self.speed * 100 / SPEED;
+                let pitch = (self.pitch * 100 / PITCH).clamp(50, 200);
+                let markup = format!(
+                    "<volume level='{}'><pitch level='{}'><speed level='{}'>{}</speed></pitch></volume>",
+                    self.volume,
+                    pitch,
+                    speed,
+                    escape_markup(text)
+                );
+                command
+                    .arg("-l")
+                    .arg(&self.lang)
+                    .arg("-w")
+                    .arg(path.as_ref())
+                    .arg("--")
+                    .arg(markup);
+            }
+        }
+
+        run(command, program)
+    }
+
+    /// Synthesise `text` and play it through the robot-hat speaker
+    pub fn say(&self, text: &str) -> Result<()> {
+        let path = temp_wav();
+        let result = self.render(text, &path).and_then(|_| play_wav(&path));
+        let _ = fs::remove_file(&path);
+
+        result
+    }
+}
+
+fn temp_wav() -> PathBuf {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let count = COUNT.fetch_add(1, Ordering::Relaxed);
+
+    env::temp_dir().join(format!("robot-hat-tts-{}-{}.wav", process::id(), count))
+}
@@ -1,17 +1,92 @@
 //! Ultrasonic module implementation
 
-use std::thread::sleep;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn, JoinHandle};
 use std::time::{Duration, Instant};
 
 use anyhow::{bail, Context, Result};
-use rppal::gpio::{InputPin, OutputPin};
+use rppal::gpio::{InputPin, OutputPin, Trigger};
 
 use crate::pin::{PinType, RHPin};
 
+// Longest echo pulse we wait for (~4m range for HC-SR04 is ~23ms)
+const ECHO_TIMEOUT: Duration = Duration::from_millis(30);
+// HC-SR04 holds echo high for ~38ms when nothing reflects, wait a little longer for it to clear
+const ECHO_IDLE_TIMEOUT: Duration = Duration::from_millis(50);
+// Valid measuring range of HC-SR04 in mm
+const MIN_RANGE: f32 = 20.0;
+const MAX_RANGE: f32 = 4000.0;
+// Default ambient temperature in °C
+const TEMPERATURE: f32 = 20.0;
+
+/// A distance measured by the [`Ultrasonic`] sensor
+#[derive(Copy, Clone, Debug, Default, PartialEq, PartialOrd)]
+pub struct Distance {
+    mm: f32,
+}
+
+impl Distance {
+    /// Create a distance from millimetres
+    pub fn from_mm(mm: f32) -> Self {
+        Self { mm }
+    }
+
+    /// Create a distance from centimetres
+    pub fn from_cm(cm: f32) -> Self {
+        Self { mm: cm * 10.0 }
+    }
+
+    /// Distance in `mm`
+    pub fn mm(&self) -> f32 {
+        self.mm
+    }
+
+    /// Distance in `cm`
+    pub fn cm(&self) -> f32 {
+        self.mm / 10.0
+    }
+
+    /// Distance in `inch`
+    pub fn inch(&self) -> f32 {
+        self.mm / 25.4
+    }
+}
+
+/// Filtering applied to consecutive [`Ultrasonic::read_distance`] pings
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Filter {
+    /// Return every valid ping as is `(default)`
+    #[default]
+    None,
+    /// Median of the last `N` valid pings
+    Median(usize),
+    /// Mean of the last `N` valid pings, excluding outliers
+    MovingAverage(usize),
+}
+
+/// How the echo pulse width is measured
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TimingMode {
+    /// Busy-poll the echo pin and time the pulse with [`Instant`] `(default)`
+    #[default]
+    Polling,
+    /// Wait for edge-triggered interrupts on the echo pin and time the pulse
+    /// using the kernel event timestamps
+    Interrupt,
+}
+
 /// Ultrasonic ranging sensor
 pub struct Ultrasonic {
     trig: OutputPin,
     echo: InputPin,
+    mode: TimingMode,
+    temperature: f32,
+    filter: Filter,
+    outlier_threshold: Option<f32>,
+    history: VecDeque<f32>,
 }
 
 impl Ultrasonic {
@@ -38,11 +113,118 @@ impl Ultrasonic {
         let trig = trig_pin.gpio_pin.into_output();
         let echo = echo_pin.gpio_pin.into_input();
 
-        Ok(Ultrasonic { trig, echo })
+        Ok(Ultrasonic {
+            trig,
+            echo,
+            mode: TimingMode::default(),
+            temperature: TEMPERATURE,
+            filter: Filter::default(),
+            outlier_threshold: None,
+            history: VecDeque::new(),
+        })
+    }
+
+    /// Set how the echo pulse width is measured `(default: TimingMode::Polling)`
+    pub fn set_timing_mode(&mut self, mode: TimingMode) -> Result<()> {
+        match mode {
+            TimingMode::Polling => self
+                .echo
+                .clear_interrupt()
+                .context("Clearing echo pin interrupt failed")?,
+            TimingMode::Interrupt => self
+                .echo
+                .set_interrupt(Trigger::Both, None)
+                .context("Setting echo pin interrupt failed")?,
+        }
+        self.mode = mode;
+
+        Ok(())
+    }
+
+    /// Get the current [`TimingMode`]
+    pub fn timing_mode(&self) -> TimingMode {
+        self.mode
+    }
+
+    /// Set the ambient temperature in `°C` used to compensate the speed of sound `(default: 20.0)`
+    pub fn set_temperature(&mut self, celsius: f32) {
+        self.temperature = celsius;
+    }
+
+    /// Set the [`Filter`] applied by [`Ultrasonic::read_distance`] `(default: Filter::None)`
+    ///
+    /// Changing the filter discards the previously collected pings.
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter;
+        self.history.clear();
+    }
+
+    /// Set the maximum deviation from the median of the collected pings for a ping to be
+    /// used by [`Filter::MovingAverage`] `(default: None)`
+    pub fn set_outlier_threshold(&mut self, threshold: Option<Distance>) {
+        self.outlier_threshold = threshold.map(|t| t.mm());
     }
 
     /// Read distance values in `cm`
-    pub fn read(&mut self) -> u64 {
+    pub fn read(&mut self) -> Result<u64> {
+        let time_taken = self.pulse()?;
+
+        // Distance in cm
+        Ok((time_taken.as_micros() / 58) as u64)
+    }
+
+    /// Read a temperature compensated and filtered [`Distance`]
+    ///
+    /// Fires a single ping; pings outside the sensor range (2 - 400cm) are rejected.
+    pub fn read_distance(&mut self) -> Result<Distance> {
+        let time_taken = self.pulse()?;
+        // speed of sound in m/s --> mm/us is `/ 1000`, and the echo travels twice the distance
+        let speed = 331.3 + 0.606 * self.temperature;
+        let mm = time_taken.as_secs_f32() * 1_000_000.0 * speed / 2000.0;
+        if !(MIN_RANGE..=MAX_RANGE).contains(&mm) {
+            bail!("Ping out of range: {:.1}mm", mm)
+        }
+
+        let window = match self.filter {
+            Filter::None => return Ok(Distance::from_mm(mm)),
+            Filter::Median(n) | Filter::MovingAverage(n) => n.max(1),
+        };
+        self.history.push_back(mm);
+        while self.history.len() > window {
+            self.history.pop_front();
+        }
+
+        let mut sorted: Vec<f32> = self.history.iter().copied().collect();
+        sorted.sort_by(f32::total_cmp);
+        let median = sorted[sorted.len() / 2];
+
+        let mm = match self.filter {
+            Filter::MovingAverage(_) => {
+                let threshold = self.outlier_threshold.unwrap_or(f32::INFINITY);
+                let inliers: Vec<f32> = sorted
+                    .into_iter()
+                    .filter(|v| (v - median).abs() <= threshold)
+                    .collect();
+                inliers.iter().sum::<f32>() / inliers.len() as f32
+            }
+            _ => median,
+        };
+
+        Ok(Distance::from_mm(mm))
+    }
+
+    fn pulse(&mut self) -> Result<Duration> {
+        match self.mode {
+            TimingMode::Polling => {
+                self.wait_echo_idle()?;
+                self.trigger();
+                self.poll_pulse()
+            }
+            TimingMode::Interrupt => self.interrupt_pulse(),
+        }
+    }
+
+    fn trigger(&mut self) {
         // Set trigger pin low for 5 us
         self.trig.set_low();
         sleep(Duration::from_micros(5));
@@ -51,16 +233,284 @@ impl Ultrasonic {
         self.trig.set_high();
         sleep(Duration::from_micros(10));
         self.trig.set_low();
+    }
+
+    // A pulse left over from a timed out ping would be measured as this ping's echo
+    fn wait_echo_idle(&mut self) -> Result<()> {
+        let deadline = Instant::now() + ECHO_IDLE_TIMEOUT;
+        while self.echo.is_high() {
+            if Instant::now() > deadline {
+                bail!("Echo pin is still high from a previous ping")
+            }
+            sleep(Duration::from_micros(100));
+        }
+
+        Ok(())
+    }
+
+    fn poll_pulse(&mut self) -> Result<Duration> {
+        let deadline = Instant::now() + ECHO_TIMEOUT;
 
         // Wait for the echo pin to go high
-        while !self.echo.is_high() {}
+        while !self.echo.is_high() {
+            if Instant::now() > deadline {
+                bail!("Timed out waiting for RisingEdge on echo pin")
+            }
+        }
 
         let pulse_start = Instant::now();
         // Wait for the echo pin to go low
-        while !self.echo.is_low() {}
+        while !self.echo.is_low() {
+            if Instant::now() > deadline {
+                bail!("Timed out waiting for FallingEdge on echo pin")
+            }
+        }
 
-        let time_taken = pulse_start.elapsed().as_micros();
-        // Distance in cm
-        (time_taken / 58) as u64
+        Ok(pulse_start.elapsed())
+    }
+
+    fn interrupt_pulse(&mut self) -> Result<Duration> {
+        // Drop any stale edges left over from a previous ping
+        self.echo
+            .poll_interrupt(true, Some(Duration::ZERO))
+            .context("Resetting echo pin interrupt failed")?;
+
+        self.trigger();
+
+        let deadline = Instant::now() + ECHO_TIMEOUT;
+        let rising = self.wait_edge(Trigger::RisingEdge, deadline)?;
+        let falling = self.wait_edge(Trigger::FallingEdge, deadline)?;
+
+        Ok(falling.saturating_sub(rising))
+    }
+
+    fn wait_edge(&mut self, edge: Trigger, deadline: Instant) -> Result<Duration> {
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let event = self
+                .echo
+                .poll_interrupt(false, Some(timeout))
+                .context("Polling echo pin interrupt failed")?;
+            match event {
+                Some(event) if event.trigger == edge => return Ok(event.timestamp),
+                Some(_) => continue,
+                None => bail!("Timed out waiting for {:?} on echo pin", edge),
+            }
+        }
+    }
+}
+
+/// A [`Distance`] sampled by [`UltrasonicSampler`] along with the time it was measured
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Reading {
+    /// The filtered distance
+    pub distance: Distance,
+    /// When the ping was fired
+    pub timestamp: Instant,
+}
+
+/// A threshold crossing reported by [`UltrasonicSampler`]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Crossing {
+    /// The distance dropped below the threshold
+    Closer(Reading),
+    /// The distance rose back above the threshold
+    Farther(Reading),
+}
+
+type Callback = Arc<Mutex<dyn FnMut(Crossing) + Send>>;
+
+struct Watcher {
+    threshold: f32,
+    hysteresis: f32,
+    // Starts `false` so an obstacle already inside the threshold is reported
+    closer: bool,
+    callback: Callback,
+}
+
+impl Watcher {
+    fn update(&mut self, reading: Reading) -> Option<Crossing> {
+        let distance = reading.distance.mm();
+        let closer = if self.closer {
+            distance <= self.threshold + self.hysteresis
+        } else {
+            distance < self.threshold
+        };
+
+        match (self.closer, closer) {
+            (false, true) => {
+                self.closer = true;
+                Some(Crossing::Closer(reading))
+            }
+            (true, false) => {
+                self.closer = false;
+                Some(Crossing::Farther(reading))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    latest: Mutex<Option<Reading>>,
+    watchers: Mutex<Vec<Watcher>>,
+    stop: AtomicBool,
+}
+
+/// Background sampler pinging an [`Ultrasonic`] sensor on its own thread
+pub struct UltrasonicSampler {
+    shared: Arc<Shared>,
+    handle: Option<JoinHandle<Ultrasonic>>,
+}
+
+impl UltrasonicSampler {
+    /// Start pinging `sensor` every `interval` on a background thread
+    ///
+    /// Readings use the sensor's [`Filter`]; failed pings are skipped.
+    pub fn new(mut sensor: Ultrasonic, interval: Duration) -> Self {
+        let shared = Arc::new(Shared::default());
+        let state = Arc::clone(&shared);
+
+        let handle = spawn(move || {
+            while !state.stop.load(Ordering::Relaxed) {
+                let timestamp = Instant::now();
+                if let Ok(distance) = sensor.read_distance() {
+                    let reading = Reading {
+                        distance,
+                        timestamp,
+                    };
+                    *state.latest.lock().unwrap() = Some(reading);
+                    // Callbacks run without holding the watchers lock
+                    let crossings: Vec<_> = state
+                        .watchers
+                        .lock()
+                        .unwrap()
+                        .iter_mut()
+                        .filter_map(|watcher| {
+                            let crossing = watcher.update(reading)?;
+                            Some((Arc::clone(&watcher.callback), crossing))
+                        })
+                        .collect();
+                    for (callback, crossing) in crossings {
+                        (callback.lock().unwrap())(crossing);
+                    }
+                }
+                sleep(interval.saturating_sub(timestamp.elapsed()));
+            }
+            sensor
+        });
+
+        Self {
+            shared,
+            handle: Some(handle),
+        }
+    }
+
+    /// Get the most recent [`Reading`] without blocking
+    ///
+    /// Returns `None` until the first successful ping.
+    pub fn latest(&self) -> Option<Reading> {
+        *self.shared.latest.lock().unwrap()
+    }
+
+    /// Call `callback` whenever the distance crosses `threshold`
+    ///
+    /// The distance has to drop below `threshold` to count as [`Crossing::Closer`] and
+    /// rise above `threshold + hysteresis` to count as [`Crossing::Farther`].
+    /// A first reading inside the threshold is reported as [`Crossing::Closer`].
+    pub fn on_threshold<F>(&self, threshold: Distance, hysteresis: Distance, callback: F)
+    where
+        F: FnMut(Crossing) + Send + 'static,
+    {
+        self.shared.watchers.lock().unwrap().push(Watcher {
+            threshold: threshold.mm(),
+            hysteresis: hysteresis.mm().abs(),
+            closer: false,
+            callback: Arc::new(Mutex::new(callback)),
+        });
+    }
+
+    /// Receive a [`Crossing`] whenever the distance crosses `threshold`
+    ///
+    /// See [`UltrasonicSampler::on_threshold`] for the `hysteresis`.
+    pub fn subscribe(&self, threshold: Distance, hysteresis: Distance) -> Receiver<Crossing> {
+        let (tx, rx) = channel();
+        self.on_threshold(threshold, hysteresis, move |crossing| {
+            let _ = tx.send(crossing);
+        });
+
+        rx
+    }
+
+    /// Stop sampling and return the [`Ultrasonic`] sensor
+    pub fn stop(mut self) -> Result<Ultrasonic> {
+        self.shutdown()
+            .context("Ultrasonic sampler thread panicked")
+    }
+
+    fn shutdown(&mut self) -> Option<Ultrasonic> {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        self.handle.take().and_then(|handle| handle.join().ok())
+    }
+}
+
+impl Drop for UltrasonicSampler {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Several [`Ultrasonic`] sensors fired one at a time to avoid cross-echoes
+pub struct UltrasonicArray {
+    sensors: Vec<Ultrasonic>,
+    gap: Duration,
+    last_ping: Option<Instant>,
+}
+
+impl UltrasonicArray {
+    /// Create an array from `sensors`, waiting at least `gap` between consecutive pings
+    pub fn new(sensors: Vec<Ultrasonic>, gap: Duration) -> Self {
+        Self {
+            sensors,
+            gap,
+            last_ping: None,
+        }
+    }
+
+    /// Set the minimum gap between consecutive pings
+    pub fn set_gap(&mut self, gap: Duration) {
+        self.gap = gap;
+    }
+
+    /// Get mutable access to the sensors, e.g. to configure their [`Filter`]
+    pub fn sensors_mut(&mut self) -> &mut [Ultrasonic] {
+        &mut self.sensors
+    }
+
+    /// Fire every sensor round-robin and return one [`Reading`] per sensor, in order
+    ///
+    /// A failed ping yields `None` for that sensor.
+    pub fn read(&mut self) -> Vec<Option<Reading>> {
+        let mut readings = Vec::with_capacity(self.sensors.len());
+        for sensor in self.sensors.iter_mut() {
+            if let Some(last_ping) = self.last_ping {
+                sleep(self.gap.saturating_sub(last_ping.elapsed()));
+            }
+            let timestamp = Instant::now();
+            self.last_ping = Some(timestamp);
+            let reading = sensor.read_distance().ok().map(|distance| Reading {
+                distance,
+                timestamp,
+            });
+            readings.push(reading);
+        }
+
+        readings
+    }
+
+    /// Consume the array and return the sensors
+    pub fn into_inner(self) -> Vec<Ultrasonic> {
+        self.sensors
     }
 }
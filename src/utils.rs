@@ -1,21 +1,45 @@
 //! Utilities for robot-hat
 
-use std::{thread::sleep, time::Duration};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::{thread::sleep, time::Duration, time::Instant};
 
 use anyhow::{Context, Result};
 use rppal::i2c::I2c;
 
+use crate::pin::{PinType, RHPin};
+
 const I2C_BUS: u8 = 1;
 const SLAVE_ADDR: u16 = 0x14;
+const MCU_RESET_PULSE: Duration = Duration::from_millis(10);
+const MCU_BOOT_TIMEOUT: Duration = Duration::from_secs(1);
+const MCU_BOOT_POLL: Duration = Duration::from_millis(10);
 
-/// Initialize robot-hat I2C
-pub fn init_i2c() -> Result<I2c> {
+// Consecutive I2C failures, and how many trigger a recovery (0 disables it)
+static FAILURES: AtomicU32 = AtomicU32::new(0);
+static RECOVERY_LIMIT: AtomicU32 = AtomicU32::new(0);
+// Number of MCU resets, so drivers can re-send settings the MCU forgot
+static RESETS: AtomicU32 = AtomicU32::new(0);
+
+fn with_bus(addr: u16) -> Result<I2c> {
     let mut i2c = I2c::with_bus(I2C_BUS).context("Constructing new I2C failed")?;
+    i2c.set_slave_address(addr)
+        .with_context(|| format!("Setting SLAVE addr {:#04x} failed", addr))?;
+
+    Ok(i2c)
+}
+
+/// Open the robot-hat I2C bus for the device at `addr`
+pub fn open_i2c(addr: u16) -> Result<I2c> {
+    let i2c = with_bus(addr)?;
     // wait after I2C init to avoid 121 IO error
     sleep(Duration::from_secs(1));
 
-    i2c.set_slave_address(SLAVE_ADDR)
-        .context("Setting SLAVE addr failed")?;
+    Ok(i2c)
+}
+
+/// Initialize robot-hat I2C
+pub fn init_i2c() -> Result<I2c> {
+    let i2c = open_i2c(SLAVE_ADDR)?;
     i2c.smbus_send_byte(0x2C)
         .context("Sending byte 0x2c failed")?;
     i2c.smbus_send_byte(0x00)
@@ -26,6 +50,83 @@ pub fn init_i2c() -> Result<I2c> {
     Ok(i2c)
 }
 
+/// Check that the robot-hat MCU answers on the I2C bus
+pub fn health_check() -> Result<()> {
+    let i2c = with_bus(SLAVE_ADDR)?;
+    i2c.smbus_receive_byte()
+        .context("MCU did not answer on I2C")?;
+
+    Ok(())
+}
+
+/// Reset the robot-hat MCU and wait until it answers on the I2C bus
+///
+/// Recovers a hung MCU (e.g. repeated 121 IO errors) without a power cycle.
+/// The MCU forgets its PWM settings; every live [`PWM`](crate::pwm::PWM) re-sends its
+/// prescaler and period before its next pulse width.
+pub fn reset_mcu() -> Result<()> {
+    let pin = RHPin::new(PinType::McuRst).context("Creating MCU reset pin failed")?;
+    let mut pin = pin.gpio_pin.into_output_high();
+    // Keep the MCU out of reset once the pin is dropped
+    pin.set_reset_on_drop(false);
+
+    pin.set_low();
+    sleep(MCU_RESET_PULSE);
+    pin.set_high();
+    sleep(MCU_RESET_PULSE);
+
+    let start = Instant::now();
+    loop {
+        match health_check() {
+            Ok(()) => break,
+            Err(err) if start.elapsed() >= MCU_BOOT_TIMEOUT => {
+                return Err(err).context("MCU did not answer after reset")
+            }
+            Err(_) => sleep(MCU_BOOT_POLL),
+        }
+    }
+    FAILURES.store(0, Ordering::Relaxed);
+    RESETS.fetch_add(1, Ordering::Relaxed);
+
+    Ok(())
+}
+
+// Number of successful MCU resets in this process
+pub(crate) fn reset_generation() -> u32 {
+    RESETS.load(Ordering::Relaxed)
+}
+
+/// Run [`health_check`] after `failures` consecutive I2C failures, calling [`reset_mcu`]
+/// if the MCU does not answer `(default: None)`
+///
+/// `None` disables automatic recovery.
+pub fn set_auto_recovery(failures: Option<u32>) {
+    RECOVERY_LIMIT.store(failures.unwrap_or(0), Ordering::Relaxed);
+}
+
+// Count consecutive failures of MCU I2C transfers for automatic recovery
+pub(crate) fn track<T>(result: Result<T>) -> Result<T> {
+    if result.is_ok() {
+        FAILURES.store(0, Ordering::Relaxed);
+        return result;
+    }
+
+    let limit = RECOVERY_LIMIT.load(Ordering::Relaxed);
+    let failures = FAILURES.fetch_add(1, Ordering::Relaxed) + 1;
+    if limit == 0 || failures < limit {
+        return result;
+    }
+    FAILURES.store(0, Ordering::Relaxed);
+    if health_check().is_ok() {
+        return result;
+    }
+
+    match reset_mcu() {
+        Ok(()) => result.with_context(|| format!("MCU was reset after {} I2C failures", failures)),
+        Err(err) => result.with_context(|| format!("MCU recovery failed: {:#}", err)),
+    }
+}
+
 /// Map value from one range (`from_range`) to another range (`to_range`)
 pub fn map_range(value: f32, from_range: (f32, f32), to_range: (f32, f32)) -> f32 {
     to_range.0 + (value - from_range.0) * (to_range.1 - to_range.0) / (from_range.1 - from_range.0)